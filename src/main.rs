@@ -1,12 +1,35 @@
+#![allow(clippy::useless_format)]
+
+use std::collections::HashMap;
 use std::io::{IsTerminal, Read};
+use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::Poll;
 use std::time::Duration;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
 use anyhow::{anyhow, Context, Error};
+use async_wsocket::{Message, WebSocket};
+use async_wsocket::futures_util::{Sink, SinkExt, StreamExt, TryStreamExt};
+use async_wsocket::futures_util::stream::SplitSink;
 use chrono::{DateTime, Local};
-
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_socks::tcp::Socks5Stream;
+use zeroize::Zeroizing;
+
+use nostr_connect::prelude::{
+    AuthUrlHandler, NostrConnect, NostrConnectKeys, NostrConnectRemoteSigner,
+    NostrConnectSignerActions
+};
 use nostr_sdk::prelude::*;
+use nostr_sdk::hashes::{sha256, Hash};
+use nostr_sdk::pool::relay::Error as RelayError;
+use nostr_sdk::pool::transport::error::TransportError;
+use nostr_sdk::pool::transport::websocket::{
+    BoxSink, BoxStream, WebSocketTransport
+};
 
 // Basic protocol flow description
 // https://github.com/nostr-protocol/nips/blob/master/01.md
@@ -29,7 +52,11 @@ enum KeyTypeFormat {
     SecretHex,
     SecretBech32,
     PublicHex,
-    PublicBech32
+    PublicBech32,
+    // nip-49 encrypted secret key
+    Ncryptsec,
+    // nip-06 bip-39 words, only for new keys
+    Mnemonic
 }
 
 impl FromStr for KeyTypeFormat {
@@ -41,467 +68,3924 @@ impl FromStr for KeyTypeFormat {
             "sbech32" => Self::SecretBech32,
             "phex" => Self::PublicHex,
             "pbech32" => Self::PublicBech32,
+            "ncryptsec" => Self::Ncryptsec,
+            "mnemonic" => Self::Mnemonic,
             _ => return Err(anyhow!("can not parse {s}"))
         })
     }
 }
 
-// utils
-
-fn path_exists(path: &str) -> bool {
-    std::path::Path::new(path).exists()
+struct KeyOptions {
+    // nip-49 scrypt cost and key security byte of ncryptsec
+    log_n: u8,
+    key_security: nip49::KeySecurity,
+    // number of words of a new mnemonic
+    words: usize,
+    // nip-06 account of the mnemonic
+    account: u32,
+    // ask for the bip-39 passphrase of the mnemonic
+    passphrase: bool
 }
 
-fn mkdir(path: &str) -> Result<(), Error> {
-    std::fs::create_dir(std::path::PathBuf::from(&path))?;
-
-    Ok(())
+impl Default for KeyOptions {
+    fn default() -> Self {
+        Self {
+            log_n: 16,
+            key_security: nip49::KeySecurity::Unknown,
+            words: 24,
+            account: 0,
+            passphrase: false
+        }
+    }
 }
 
-fn file_write(file_name: &str, content: &str) -> Result<(), Error> {
-    Ok(std::fs::write(file_name, content)?)
+// plaintext of nip44-encrypt and nip44-decrypt, as is or base64 for binary
+enum PayloadFormat {
+    Raw,
+    Base64
 }
 
-fn stdin_pipe_read() -> Result<String, Error> {
-    let mut input = std::io::stdin();
+impl FromStr for PayloadFormat {
+    type Err = Error;
 
-    if input.is_terminal() {
-        return Err(anyhow!("stdin is empty"));
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "raw" => Self::Raw,
+            "base64" => Self::Base64,
+            _ => return Err(anyhow!("can not parse {s}"))
+        })
     }
-
-    let mut output = String::new();
-
-    input.read_to_string(&mut output)?;
-
-    Ok(output)
 }
 
-fn datetime_human_readable_format_get() -> &'static str {
-    "%Y/%m/%d %H:%M:%S"
+#[derive(Default)]
+struct EventsSendOptions {
+    // minimum number of relays that should accept each event
+    min_success: usize,
+    // directory where events not reaching min_success are stored
+    outbox: Option<String>,
+    // use new tor circuits instead of the ones of previous runs
+    new_circuit: bool,
+    // send every event with its own connections and circuits
+    separate: bool,
+    // maximum random delay in seconds before sending every event after the
+    // first one
+    delay: u64,
+    // gift wraps to this key, the self copies of the dms, are sent last,
+    // waiting self_delay seconds before sending them
+    self_public_key: Option<PublicKey>,
+    self_delay: u64,
+    // identity whose connections and circuits are used, instead of
+    // self_public_key for gift wraps and the author for the other events
+    identity: Option<PublicKey>,
+    // wrap keys file where the relays of its gift wraps are recorded
+    wrap_keys: Option<String>
 }
 
-fn unix_timestamp_s_to_string(timestamp: u64) -> Result<String, Error> {
-    let datetime =
-        DateTime::from_timestamp(timestamp as i64, 0)
-            .ok_or(anyhow!("datetime from timestamp seconds"))?
-            .with_timezone(&Local);
-
-    Ok(datetime.format(datetime_human_readable_format_get()).to_string())
+// event waiting in the outbox to be accepted by min_success relays
+#[derive(serde::Serialize, serde::Deserialize)]
+struct OutboxEntry {
+    event: Event,
+    relays: Vec<String>,
+    accepted: Vec<String>,
+    min_success: usize,
+    attempts: u64,
+    // identity whose connections and circuits are used, the author if not
+    // present
+    #[serde(default)]
+    identity: Option<PublicKey>
 }
 
-fn u64_from_serde_value(
-    object: &serde_json::Value, key: &str
-) -> Result<u64, Error> {
-    Ok(object.get(key)
-        .ok_or(anyhow!("{key} not present"))?
-        .as_number()
-        .ok_or(anyhow!("{key} not number"))?
-        .as_u64()
-        .ok_or(anyhow!("{key} not u64"))?
-    )
+// how to connect to a relay
+#[derive(Clone)]
+enum ProxyMode {
+    Direct,
+    Socks {
+        // host:port
+        proxy: String,
+        credentials: Option<(String, String)>
+    }
 }
 
-fn timeout_get() -> Duration {
-    Duration::from_secs(60)
+// the proxy password is not shown
+impl std::fmt::Debug for ProxyMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Direct => f.write_str("Direct"),
+            Self::Socks { proxy, credentials } => f.debug_struct("Socks")
+                .field("proxy", proxy)
+                .field("username", &credentials.as_ref()
+                    .map(|(username, _)| username)
+                )
+                .finish_non_exhaustive()
+        }
+    }
 }
 
-// actions
+// direct or [username:password@]host:port
+impl FromStr for ProxyMode {
+    type Err = Error;
 
-fn key_convert(key: &str, key_type_format: KeyTypeFormat) -> Result<(), Error> {
-    let key_output = if let Ok(keys) = Keys::parse(key) {
-        match key_type_format {
-            KeyTypeFormat::SecretHex => keys.secret_key().to_secret_hex(),
-            KeyTypeFormat::SecretBech32 => keys.secret_key().to_bech32()?,
-            KeyTypeFormat::PublicHex => keys.public_key().to_hex(),
-            KeyTypeFormat::PublicBech32 => keys.public_key().to_bech32()?
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "direct" {
+            return Ok(Self::Direct);
         }
-    } else if let Ok(public_key) = PublicKey::parse(key) {
-        match key_type_format {
-            KeyTypeFormat::SecretHex|KeyTypeFormat::SecretBech32 =>
-                return Err(anyhow!("can not get private key from public key")),
-            KeyTypeFormat::PublicHex => public_key.to_hex(),
-            KeyTypeFormat::PublicBech32 => public_key.to_bech32()?
+
+        let (credentials, proxy) = match s.rsplit_once('@') {
+            Some((credentials, proxy)) => {
+                let (username, password) = credentials.split_once(':')
+                    .ok_or(anyhow!("proxy credentials not username:password"))?;
+                (Some((username.to_owned(), password.to_owned())), proxy)
+            },
+            None => (None, s)
+        };
+
+        let (host, port) = proxy.rsplit_once(':')
+            .ok_or(anyhow!("proxy address {proxy} not host:port"))?;
+        if host.is_empty() {
+            return Err(anyhow!("proxy address {proxy} without host"));
         }
-    } else {
-        return Err(anyhow!("stdin is not a secret key nor a public key"));
-    };
+        port.parse::<u16>()
+            .with_context(|| format!("parsing proxy port {port}"))?;
 
-    println!("{key_output}");
+        Ok(Self::Socks {
+            proxy: proxy.to_owned(),
+            credentials
+        })
+    }
+}
 
-    Ok(())
+// websocket transport connecting to every relay with its proxy mode
+#[derive(Debug)]
+struct ProxyTransport {
+    default: ProxyMode,
+    // parsed relay url -> proxy mode
+    relays: HashMap<String, ProxyMode>,
+    // when the socks credentials are not configured they are derived from
+    // the relay and the isolation, so tor with IsolateSOCKSAuth uses a
+    // different circuit for every relay and identity
+    isolation: String
 }
 
-fn unsigned_event_print(
-    event: UnsignedEvent,
-    extra_fields: Option<JsonOrdered>
-) -> Result<(), Error> {
-    let mut event_json = JsonOrdered::new();
+impl ProxyTransport {
+    fn mode_get(&self, url: &Url) -> ProxyMode {
+        match self.relays.get(url.as_str()).unwrap_or(&self.default) {
+            ProxyMode::Socks { proxy, credentials: None } => ProxyMode::Socks {
+                proxy: proxy.clone(),
+                credentials: Some((
+                    sha256::Hash::hash(url.as_str().as_bytes()).to_string(),
+                    sha256::Hash::hash(self.isolation.as_bytes()).to_string()
+                ))
+            },
+            mode => mode.clone()
+        }
+    }
+}
 
-    event_json.insert(
-        format!("id"),
-        serde_json::json!(event.id)
-    );
+impl WebSocketTransport for ProxyTransport {
+    fn support_ping(&self) -> bool {
+        true
+    }
 
-    event_json.insert(
-        format!("pubkey"),
-        serde_json::json!({
-            "bech32": event.pubkey.to_bech32()?,
-            "hex": event.pubkey.to_hex()
-        })
-    );
+    fn connect<'a>(
+        &'a self,
+        url: &'a Url,
+        _mode: &'a ConnectionMode,
+        timeout: Duration
+    ) -> BoxedFuture<'a, Result<(BoxSink, BoxStream), TransportError>> {
+        Box::pin(async move {
+            let socket = websocket_connect(url, &self.mode_get(url), timeout)
+                .await
+                .map_err(|error| TransportError::Backend(error.into()))?;
 
-    event_json.insert(
-        format!("created_at"),
-        serde_json::json!({
-            "timestamp": event.created_at.as_u64(),
-            "date": serde_json::json!(
-                unix_timestamp_s_to_string(event.created_at.as_u64())?
-            )
+            let (tx, rx) = socket.split();
+
+            let sink: BoxSink = Box::new(ProxyTransportSink(tx)) as BoxSink;
+            let stream: BoxStream =
+                Box::new(rx.map_err(TransportError::backend)) as BoxStream;
+
+            Ok((sink, stream))
         })
-    );
+    }
+}
 
-    event_json.insert(
-        format!("kind"),
-        serde_json::json!(event.kind)
-    );
+struct ProxyTransportSink(SplitSink<WebSocket, Message>);
 
-    event_json.insert(
-        format!("tags"),
-        serde_json::json!(event.tags.as_slice())
-    );
+impl Sink<Message> for ProxyTransportSink {
+    type Error = TransportError;
 
-    event_json.insert(
-        format!("content"),
-        serde_json::from_str::<serde_json::Value>(&event.content)
-            .unwrap_or_else(|_| serde_json::json!(&event.content))
-    );
+    fn poll_ready(
+        mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_ready_unpin(cx)
+            .map_err(TransportError::backend)
+    }
 
-    if let Some(extra_fields) = extra_fields {
-        event_json.extend(extra_fields);
+    fn start_send(
+        mut self: Pin<&mut Self>, item: Message
+    ) -> Result<(), Self::Error> {
+        Pin::new(&mut self.0)
+            .start_send_unpin(item)
+            .map_err(TransportError::backend)
     }
 
-    println!("{}", serde_json::to_string_pretty(&event_json)?);
+    fn poll_flush(
+        mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_flush_unpin(cx)
+            .map_err(TransportError::backend)
+    }
 
-    Ok(())
+    fn poll_close(
+        mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0)
+            .poll_close_unpin(cx)
+            .map_err(TransportError::backend)
+    }
 }
 
-// create client with tor, connect it to the relays and return it
-async fn client_connected_relays_get(
-    relays_list: &Vec<Vec<String>>
-) -> Result<Client, Error> {
-    let timeout = timeout_get();
-
-    let tor_socket: SocketAddr = SocketAddr::new(
-        IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9050
-    );
+#[derive(Default)]
+struct PowOptions {
+    // nip-13 difficulty
+    difficulty: u8,
+    // the difficulty is raised to the min_pow_difficulty (nip-11) of these
+    // relays
+    relays: Vec<String>
+}
 
-    let client = Client::builder()
-        .opts(ClientOptions::new()
-            .gossip(false)
-            .connection(Connection::new().proxy(tor_socket))
-        ).build();
+impl PowOptions {
+    fn from_json(options: &serde_json::Value) -> Result<Self, Error> {
+        let mut pow_options = Self::default();
 
-    for relays in relays_list {
-        for relay in relays {
-            client.add_relay(relay).await?;
+        if let Ok(value) = u64_from_serde_value(options, "pow") {
+            pow_options.difficulty = u8::try_from(value)
+                .with_context(|| "pow not u8")?;
         }
-    }
-    for (key, value) in client.try_connect(timeout).await.failed {
-        eprintln!("error: {key} connecting {value}");
+        if let Some(value) = options.get("pow_relays") {
+            pow_options.relays = serde_json::from_value(value.clone())
+                .with_context(|| "parsing pow_relays")?;
+        }
+
+        Ok(pow_options)
     }
 
-    Ok(client)
+    // the relays are requested on the circuits of the identity
+    async fn difficulty_get(&self, identity: &PublicKey) -> Result<u8, Error> {
+        let mut difficulty = self.difficulty;
+
+        for relay in &self.relays {
+            let information = relay_information_get(
+                relay, &isolation_get(&[*identity], false)
+            ).await
+                .with_context(|| format!("fetching information of {relay}"))?;
+
+            if let Some(min_pow_difficulty) = information.limitation
+                .and_then(|limitation| limitation.min_pow_difficulty) {
+                difficulty = difficulty.max(
+                    u8::try_from(min_pow_difficulty.max(0)).unwrap_or(u8::MAX)
+                );
+            }
+        }
+
+        if difficulty > pow_difficulty_max_get() {
+            return Err(anyhow!(
+                "pow difficulty {difficulty} larger than {}",
+                pow_difficulty_max_get()
+            ));
+        }
+
+        Ok(difficulty)
+    }
 }
 
-async fn events_fetch_filter(
-    filter: Filter, relays: Vec<String>
-) -> Result<Events, Error> {
-    let timeout = timeout_get();
+#[derive(Default)]
+struct EventOptions {
+    pow: PowOptions
+}
 
-    let client = client_connected_relays_get(&vec![relays]).await?;
+// event to send when send_at is reached
+#[derive(serde::Serialize, serde::Deserialize)]
+struct ScheduleEntry {
+    send_at: u64,
+    event: Event,
+    relays: Vec<String>
+}
 
-    let events: Events = client
-        .fetch_events(filter, timeout)
-        .await?;
+#[derive(Default)]
+struct DmEventsOptions {
+    // created_at of the rumor, when the message is scheduled
+    created_at: Option<u64>,
+    // pow of the gift wraps
+    pow: PowOptions,
+    // seconds after created_at when the rumor and the gift wraps expire
+    expiration: Option<u64>,
+    // file where the ephemeral keys of the gift wraps are appended
+    wrap_keys: Option<String>
+}
 
-    client.disconnect().await;
+#[derive(Default)]
+struct VanishOptions {
+    // request to vanish from every relay (ALL_RELAYS) instead of the given
+    // relays and our inbox relays
+    all_relays: bool,
+    reason: String,
+    // our public key, confirming without the interactive prompt
+    confirm: Option<String>
+}
 
-    Ok(events)
+// seal and rumor of a gift wrap, with the errors of each layer
+struct GiftWrapLayers {
+    seal: Event,
+    seal_errors: Vec<String>,
+    // as received, to show a signature that a rumor should not have
+    rumor: JsonOrdered,
+    rumor_errors: Vec<String>
 }
 
-async fn events_send(
-    events: Vec<Event>, relays_list: Vec<Vec<String>>
-) -> Result<(), Error> {
-    if relays_list.len() != 1 && relays_list.len() != events.len() {
-        return Err(anyhow!(
-            "relays list should be len 1 or the same \
-             as the len of the events"
-        ));
-    }
+#[derive(serde::Serialize)]
+struct GiftWrapLayerReport {
+    valid: bool,
+    errors: Vec<String>,
+    event: JsonOrdered
+}
 
-    let client = client_connected_relays_get(&relays_list).await?;
+#[derive(serde::Serialize)]
+struct GiftUnwrapReport {
+    event: usize,
+    id: EventId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seal: Option<GiftWrapLayerReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rumor: Option<GiftWrapLayerReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>
+}
 
-    for i in 0..events.len() {
-        let relays = if relays_list.len() == 1 {
-            &relays_list[0]
-        } else {
-            &relays_list[i]
-        };
+// unsigned event read by events-sign and gift-wrap
+#[derive(serde::Deserialize)]
+struct EventTemplate {
+    kind: Kind,
+    #[serde(default)]
+    tags: Vec<Tag>,
+    #[serde(default)]
+    content: String,
+    created_at: Option<Timestamp>
+}
 
-        for (key, value) in client.send_event_to(
-            relays, &events[i]
-        ).await?.failed {
-            eprintln!("error: {key} sending event {value}");
-        }
+#[derive(Default)]
+struct SignOptions {
+    // created_at of the events without one, now if not set
+    created_at: Option<u64>,
+    pow: PowOptions,
+    // seconds after created_at when the events expire
+    expiration: Option<u64>
+}
 
-        println!("event {} sent", i + 1);
-    }
+#[derive(Default)]
+struct DeleteOptions {
+    reason: Option<String>
+}
 
-    client.disconnect().await;
+// what a bunker client can ask, connect, get_public_key and ping are always
+// allowed
+#[derive(Default, serde::Deserialize)]
+struct BunkerPermissions {
+    // kinds of the events the client can sign
+    #[serde(default)]
+    kinds: Vec<Kind>,
+    #[serde(default)]
+    nip04_encrypt: bool,
+    #[serde(default)]
+    nip04_decrypt: bool,
+    #[serde(default)]
+    nip44_encrypt: bool,
+    #[serde(default)]
+    nip44_decrypt: bool
+}
 
-    Ok(())
+#[derive(Default)]
+struct AgentOptions {
+    // seconds the agent holds the keys, then it exits
+    lifetime: Option<u64>,
+    // seconds without requests after which the agent exits
+    idle_timeout: Option<u64>,
+    // every request, except get_public_key, confirmed in the terminal of the
+    // agent
+    confirm: bool
 }
 
-fn metadata_event(
-    metadata: Metadata, private_key: &str
-) -> Result<(), Error> {
-    let keys = Keys::parse(private_key)?;
-    let event = EventBuilder::metadata(&metadata).sign_with_keys(&keys)?;
+// request to the agent, a json line on its socket
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AgentRequest {
+    method: String,
+    // key of the agent used
+    public_key: PublicKey,
+    // public key of the other side of encryption and decryption
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    peer: Option<PublicKey>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    event: Option<UnsignedEvent>
+}
 
-    println!("{}", event.as_pretty_json());
+// response of the agent, a json line with result or error
+#[derive(serde::Serialize, serde::Deserialize)]
+struct AgentResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    result: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>
+}
 
-    Ok(())
+// keys held by the agent, the vector in locked memory
+struct AgentState {
+    keys: Vec<Keys>,
+    confirm: bool,
+    // one confirmation at a time in the terminal
+    confirm_lock: tokio::sync::Mutex<()>
 }
 
-// currently specifying just read/write for nip-65 not supported
-fn relay_list_event(
-    kind: Kind, private_key: &str, relays: Vec<String>
-) -> Result<(), Error> {
-    let keys = Keys::parse(private_key)?;
+// signer using a key of the agent in NMINI_AGENT
+#[derive(Debug)]
+struct AgentSigner {
+    socket: String,
+    public_key: PublicKey
+}
 
-    let tag_kind = TagKind::Custom(std::borrow::Cow::Borrowed(match &kind {
-        Kind::RelayList => "r",
-        Kind::InboxRelays => "relay",
-        _ => return Err(anyhow!("wrong kind"))
-    }));
+impl AgentSigner {
+    async fn request(
+        &self, method: &str, peer: Option<PublicKey>, content: Option<String>,
+        event: Option<UnsignedEvent>
+    ) -> Result<serde_json::Value, Error> {
+        use tokio::io::AsyncBufReadExt;
+
+        let mut request = serde_json::to_string(&AgentRequest {
+            method: method.to_owned(),
+            public_key: self.public_key,
+            peer,
+            content,
+            event
+        })?;
+        request += "\n";
+
+        let mut response = String::new();
+        tokio::time::timeout(timeout_get(), async {
+            let mut stream = tokio::net::UnixStream::connect(&self.socket)
+                .await
+                .with_context(|| {
+                    format!("connecting to agent {}", self.socket)
+                })?;
+            stream.write_all(request.as_bytes()).await?;
+            tokio::io::BufReader::new(stream).read_line(&mut response).await?;
+            Ok::<(), Error>(())
+        }).await.with_context(|| "agent timed out")??;
+
+        let response: AgentResponse = serde_json::from_str(&response)
+            .with_context(|| "parsing agent response")?;
+        if let Some(error) = response.error {
+            return Err(anyhow!("agent: {error}"));
+        }
 
-    let mut builder = EventBuilder::new(kind, "");
-    for relay in relays {
-        builder = builder.tag(Tag::custom(tag_kind.clone(), [relay]));
+        response.result.ok_or(anyhow!("agent response without result"))
     }
 
-    let event = builder.sign_with_keys(&keys)?;
-
-    println!("{}", event.as_pretty_json());
+    async fn cipher_request(
+        &self, method: &str, peer: &PublicKey, content: &str
+    ) -> Result<String, SignerError> {
+        let result = self.request(
+            method, Some(*peer), Some(content.to_owned()), None
+        ).await.map_err(|error| SignerError::from(format!("{error:#}")))?;
 
-    Ok(())
+        result.as_str()
+            .map(|result| result.to_owned())
+            .ok_or(SignerError::from("agent result not str"))
+    }
 }
 
-fn filter_add_options(
-    mut filter: Filter, since: Option<u64>, until: Option<u64>
-) -> Filter {
-    if let Some(since) = since {
-        filter = filter.since(Timestamp::from(since))
+impl NostrSigner for AgentSigner {
+    fn backend(&self) -> SignerBackend<'_> {
+        SignerBackend::Custom(std::borrow::Cow::Borrowed("nmini agent"))
     }
-    if let Some(until) = until {
-        filter = filter.until(Timestamp::from(until))
+
+    fn get_public_key(
+        &self
+    ) -> BoxedFuture<'_, Result<PublicKey, SignerError>> {
+        Box::pin(async move { Ok(self.public_key) })
     }
 
-    filter
-}
+    fn sign_event(
+        &self, unsigned: UnsignedEvent
+    ) -> BoxedFuture<'_, Result<Event, SignerError>> {
+        Box::pin(async move {
+            let result = self.request("sign_event", None, None, Some(unsigned))
+                .await
+                .map_err(|error| SignerError::from(format!("{error:#}")))?;
 
-async fn events_fetch(
-    kinds: Vec<Kind>, public_key: &str, relays: Vec<String>,
-    since: Option<u64>, until: Option<u64>
-) -> Result<(), Error> {
-    let mut filter: Filter = Filter::new()
-        .authors([PublicKey::parse(public_key)?])
-        .kinds(kinds);
-    filter = filter_add_options(filter, since, until);
+            serde_json::from_value(result).map_err(SignerError::backend)
+        })
+    }
 
-    let events = events_fetch_filter(filter, relays).await?;
+    fn nip04_encrypt<'a>(
+        &'a self, public_key: &'a PublicKey, content: &'a str
+    ) -> BoxedFuture<'a, Result<String, SignerError>> {
+        Box::pin(self.cipher_request("nip04_encrypt", public_key, content))
+    }
 
-    for event in events.to_vec() {
-        println!("{}", event.as_pretty_json());
+    fn nip04_decrypt<'a>(
+        &'a self, public_key: &'a PublicKey, encrypted_content: &'a str
+    ) -> BoxedFuture<'a, Result<String, SignerError>> {
+        Box::pin(
+            self.cipher_request("nip04_decrypt", public_key, encrypted_content)
+        )
     }
 
-    Ok(())
-}
+    fn nip44_encrypt<'a>(
+        &'a self, public_key: &'a PublicKey, content: &'a str
+    ) -> BoxedFuture<'a, Result<String, SignerError>> {
+        Box::pin(self.cipher_request("nip44_encrypt", public_key, content))
+    }
 
-fn rumors_info<T>(
-    rumors: Vec<T>
-) -> Result<(), Error>
-where
-    T: Into<UnsignedEvent>
-{
-    for rumor in rumors {
-        unsigned_event_print(rumor.into(), None)?;
+    fn nip44_decrypt<'a>(
+        &'a self, public_key: &'a PublicKey, payload: &'a str
+    ) -> BoxedFuture<'a, Result<String, SignerError>> {
+        Box::pin(self.cipher_request("nip44_decrypt", public_key, payload))
     }
+}
 
-    Ok(())
+// ephemeral key of a sent gift wrap, encrypted to our own key
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WrapKeyEntry {
+    id: EventId,
+    pubkey: PublicKey,
+    key: String,
+    // relays the gift wrap was sent to, recorded by events-send
+    #[serde(default)]
+    relays: Vec<String>
 }
 
-// https://github.com/nostr-protocol/nips/blob/master/17.md
-//
-// TODO: support tags:
-// ["e", "<kind-14-id>", "<relay-url>"] // if this is a reply
-// ["subject", "<conversation-title>"]
-// ["q", "<event-id> or <event-address>", "<relay-url>", "<pubkey-if-a-regular-event>"]
-//
-// TODO: support Kind::Custom(15)
-async fn dm_events(
-    private_key: &str, receiver_public_key: &str, message: &str
-) -> Result<(), Error> {
-    let keys = Keys::parse(private_key)?;
-    let receiver = PublicKey::parse(receiver_public_key)?;
+// utils
+
+fn path_exists(path: &str) -> bool {
+    std::path::Path::new(path).exists()
+}
+
+fn mkdir(path: &str) -> Result<(), Error> {
+    std::fs::create_dir(std::path::PathBuf::from(&path))?;
+
+    Ok(())
+}
+
+fn file_write(file_name: &str, content: &str) -> Result<(), Error> {
+    Ok(std::fs::write(file_name, content)?)
+}
+
+// append to a file only readable by the user, creating it if missing
+fn file_append(file_name: &str, content: &str) -> Result<(), Error> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(file_name)?;
+    file.write_all(content.as_bytes())?;
+
+    Ok(())
+}
+
+fn file_read(file_name: &str) -> Result<String, Error> {
+    Ok(std::fs::read_to_string(file_name)?)
+}
+
+fn file_remove(file_name: &str) -> Result<(), Error> {
+    Ok(std::fs::remove_file(file_name)?)
+}
+
+// sorted paths of the files in a directory
+fn dir_files_get(path: &str) -> Result<Vec<String>, Error> {
+    let mut files: Vec<String> = Vec::new();
+
+    for entry in std::fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            files.push(entry.path().to_string_lossy().into_owned());
+        }
+    }
+    files.sort();
+
+    Ok(files)
+}
+
+fn stdin_pipe_read() -> Result<String, Error> {
+    let mut input = std::io::stdin();
+
+    if input.is_terminal() {
+        return Err(anyhow!("stdin is empty"));
+    }
+
+    let mut output = String::new();
+
+    input.read_to_string(&mut output)?;
+
+    Ok(output)
+}
+
+fn datetime_human_readable_format_get() -> &'static str {
+    "%Y/%m/%d %H:%M:%S"
+}
+
+fn unix_timestamp_s_to_string(timestamp: u64) -> Result<String, Error> {
+    let datetime =
+        DateTime::from_timestamp(timestamp as i64, 0)
+            .ok_or(anyhow!("datetime from timestamp seconds"))?
+            .with_timezone(&Local);
+
+    Ok(datetime.format(datetime_human_readable_format_get()).to_string())
+}
+
+fn u64_from_serde_value(
+    object: &serde_json::Value, key: &str
+) -> Result<u64, Error> {
+    object.get(key)
+        .ok_or(anyhow!("{key} not present"))?
+        .as_number()
+        .ok_or(anyhow!("{key} not number"))?
+        .as_u64()
+        .ok_or(anyhow!("{key} not u64"))
+}
+
+// read a line from the terminal, since stdin is used for keys and events
+fn tty_line_read(prompt: &str) -> Result<String, Error> {
+    use std::io::{BufRead, Write};
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .with_context(|| "opening terminal")?;
+    tty.write_all(prompt.as_bytes())?;
+    tty.flush()?;
+
+    let mut line = String::new();
+    std::io::BufReader::new(tty).read_line(&mut line)?;
+
+    Ok(line.trim().to_owned())
+}
+
+// reading the terminal from the background would stop the process
+// (SIGTTIN), so it is checked to be in the foreground
+fn tty_foreground_check() -> Result<(), Error> {
+    use std::os::fd::AsRawFd;
+
+    let tty = std::fs::File::open("/dev/tty")
+        .with_context(|| "opening terminal")?;
+    if unsafe { libc::tcgetpgrp(tty.as_raw_fd()) != libc::getpgrp() } {
+        return Err(anyhow!("not in the foreground of the terminal"));
+    }
+
+    Ok(())
+}
+
+// read a line from the terminal without echo
+fn tty_password_read(prompt: &str) -> Result<Zeroizing<String>, Error> {
+    use std::io::Write;
+    use std::os::fd::AsRawFd;
+
+    let mut tty = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/tty")
+        .with_context(|| "opening terminal")?;
+    let fd = tty.as_raw_fd();
+
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(anyhow!("getting terminal attributes"));
+    }
+    let mut termios_no_echo = termios;
+    termios_no_echo.c_lflag &= !libc::ECHO;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios_no_echo) } != 0 {
+        return Err(anyhow!("disabling terminal echo"));
+    }
+
+    let result = tty.write_all(prompt.as_bytes())
+        .and_then(|_| tty.flush())
+        .map_err(Error::from)
+        .and_then(|_| fd_secret_line_read(fd));
+
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) };
+    tty.write_all(b"\n")?;
+
+    result
+}
+
+// lock the memory of the vector, also its unused capacity, so it is never
+// swapped
+fn memory_lock<T>(vector: &Vec<T>) -> Result<(), Error> {
+    if unsafe {
+        libc::mlock(
+            vector.as_ptr() as *const libc::c_void,
+            vector.capacity() * std::mem::size_of::<T>()
+        )
+    } != 0 {
+        return Err(anyhow!(
+            "locking memory: {}", std::io::Error::last_os_error()
+        ));
+    }
+
+    Ok(())
+}
+
+fn fd_secret_line_read(fd: i32) -> Result<Zeroizing<String>, Error> {
+    Ok(fd_secret_line_next(fd)?.unwrap_or_default())
+}
+
+// read a line with a secret from the file descriptor a byte at a time, so
+// nothing after it is read and no buffer is left behind, in memory allocated
+// once, locked when the limit allows it and wiped when dropped, none at the
+// end
+fn fd_secret_line_next(fd: i32) -> Result<Option<Zeroizing<String>>, Error> {
+    use std::os::fd::FromRawFd;
+
+    let mut file = std::mem::ManuallyDrop::new(
+        unsafe { std::fs::File::from_raw_fd(fd) }
+    );
+
+    let mut line: Zeroizing<Vec<u8>> =
+        Zeroizing::new(Vec::with_capacity(4096));
+    memory_lock(&line).ok();
+
+    let mut byte = Zeroizing::new([0u8; 1]);
+    let mut end = true;
+    while file.read(&mut *byte).with_context(|| "reading secret")? == 1 {
+        end = false;
+        if byte[0] == b'\n' {
+            break;
+        }
+        if line.len() == line.capacity() {
+            return Err(anyhow!("secret line too long"));
+        }
+        line.push(byte[0]);
+    }
+    if end {
+        return Ok(None);
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+
+    std::str::from_utf8(&line).with_context(|| "secret not utf-8")?;
+
+    Ok(Some(Zeroizing::new(String::from_utf8(std::mem::take(&mut *line))?)))
+}
+
+// read a line from the file descriptor in NMINI_PASSWORD_FD, a byte at a
+// time so the next password read gets the next line
+fn fd_password_read(fd: &str) -> Result<Zeroizing<String>, Error> {
+    fd_secret_line_read(
+        fd.parse().with_context(|| "parsing NMINI_PASSWORD_FD")?
+    ).with_context(|| "reading password fd")
+}
+
+// password from NMINI_PASSWORD_FD if set, otherwise typed in the terminal,
+// twice when confirm is set
+fn password_read(
+    prompt: &str, confirm: bool
+) -> Result<Zeroizing<String>, Error> {
+    if let Ok(fd) = std::env::var("NMINI_PASSWORD_FD") {
+        return fd_password_read(&fd);
+    }
+
+    let password = tty_password_read(prompt)?;
+    if confirm && *tty_password_read("repeat password: ")? != *password {
+        return Err(anyhow!("passwords do not match"));
+    }
+
+    Ok(password)
+}
+
+// no core dumps with the secrets in memory, and the memory not readable by
+// other processes of the user
+fn core_dumps_disable() {
+    unsafe {
+        libc::setrlimit(libc::RLIMIT_CORE, &libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0
+        });
+        libc::prctl(libc::PR_SET_DUMPABLE, 0);
+    }
+}
+
+// secret key formats are not printed in a terminal, where they stay in the
+// scrollback, unless NMINI_SECRET_TTY is 1
+fn secret_output_check(key_type_format: &KeyTypeFormat) -> Result<(), Error> {
+    if matches!(
+        key_type_format,
+        KeyTypeFormat::SecretHex|KeyTypeFormat::SecretBech32|
+        KeyTypeFormat::Mnemonic
+    ) && std::io::stdout().is_terminal()
+        && std::env::var("NMINI_SECRET_TTY").as_deref() != Ok("1") {
+        return Err(anyhow!(
+            "not printing a secret in the terminal, redirect stdout or set \
+             NMINI_SECRET_TTY=1"
+        ));
+    }
+
+    Ok(())
+}
+
+// mining more than this would take hours
+fn pow_difficulty_max_get() -> u8 {
+    32
+}
+
+fn timeout_get() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn tor_socket_get() -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 9050)
+}
+
+// proxy used for every relay, NMINI_PROXY can be direct for clearnet
+// connections or [username:password@]host:port, tor on 127.0.0.1:9050 if
+// not set
+// NMINI_RELAYS_PROXY is a json object of relay urls with the proxy used for
+// that relay, in the same format of NMINI_PROXY
+fn proxy_transport_get(isolation: &str) -> Result<ProxyTransport, Error> {
+    let default = match std::env::var("NMINI_PROXY") {
+        Ok(proxy) => ProxyMode::from_str(&proxy)
+            .with_context(|| "parsing NMINI_PROXY")?,
+        Err(_) => ProxyMode::Socks {
+            proxy: tor_socket_get().to_string(),
+            credentials: None
+        }
+    };
+
+    let mut relays: HashMap<String, ProxyMode> = HashMap::new();
+    if let Ok(relays_proxy) = std::env::var("NMINI_RELAYS_PROXY") {
+        let relays_proxy: HashMap<String, String> =
+            serde_json::from_str(&relays_proxy)
+                .with_context(|| "parsing NMINI_RELAYS_PROXY")?;
+
+        for (relay, proxy) in relays_proxy {
+            relays.insert(
+                Url::parse(&relay)
+                    .with_context(|| format!("parsing relay {relay}"))?
+                    .to_string(),
+                ProxyMode::from_str(&proxy)
+                    .with_context(|| format!("parsing proxy of {relay}"))?
+            );
+        }
+    }
+
+    Ok(ProxyTransport {
+        default,
+        relays,
+        isolation: isolation.to_owned()
+    })
+}
+
+// tcp stream to the host of the url, directly or through the proxy
+async fn tcp_stream_connect(
+    url: &Url, mode: &ProxyMode, timeout: Duration
+) -> Result<tokio::net::TcpStream, Error> {
+    let host = url.host_str()
+        .ok_or(anyhow!("url without host"))?;
+    let port = url.port_or_known_default()
+        .ok_or(anyhow!("url without port"))?;
+
+    Ok(match mode {
+        ProxyMode::Direct => tokio::time::timeout(
+            timeout, tokio::net::TcpStream::connect((host, port))
+        ).await
+            .with_context(|| "connecting timed out")?
+            .with_context(|| format!("connecting to {host}:{port}"))?,
+        ProxyMode::Socks { proxy, credentials } => tokio::time::timeout(
+            timeout, async {
+                match credentials {
+                    Some((username, password)) =>
+                        Socks5Stream::connect_with_password(
+                            proxy.as_str(), (host, port), username, password
+                        ).await,
+                    None => Socks5Stream::connect(
+                        proxy.as_str(), (host, port)
+                    ).await
+                }
+            }
+        ).await
+            .with_context(|| "connecting to proxy timed out")?
+            .with_context(|| format!("connecting to proxy {proxy}"))?
+            .into_inner()
+    })
+}
+
+async fn websocket_connect(
+    url: &Url, mode: &ProxyMode, timeout: Duration
+) -> Result<WebSocket, Error> {
+    let tcp_stream = tcp_stream_connect(url, mode, timeout).await?;
+
+    let (stream, _) = tokio::time::timeout(
+        timeout,
+        tokio_tungstenite::client_async_tls(url.as_str(), tcp_stream)
+    ).await
+        .with_context(|| "connecting timed out")??;
+
+    Ok(WebSocket::Tokio(stream))
+}
+
+// head and body of the response to a get request with http/1.0, so the
+// response is not chunked
+async fn http_get(
+    url: &Url, mode: &ProxyMode, accept: &str, timeout: Duration
+) -> Result<(String, String), Error> {
+    let tls = match url.scheme() {
+        "https" => true,
+        "http" => false,
+        scheme => return Err(anyhow!("{scheme} is not an http scheme"))
+    };
+
+    let host = url.host_str()
+        .ok_or(anyhow!("url without host"))?
+        .to_owned();
+    // the port only when it is not the default one of the scheme
+    let host_header = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.clone()
+    };
+    let path = match url.query() {
+        Some(query) => format!("{}?{query}", url.path()),
+        None => url.path().to_owned()
+    };
+    let request = format!(
+        "GET {path} HTTP/1.0\r\n\
+         Host: {host_header}\r\n\
+         Accept: {accept}\r\n\r\n"
+    );
+
+    let tcp_stream = tcp_stream_connect(url, mode, timeout).await?;
+
+    let mut response: Vec<u8> = Vec::new();
+    tokio::time::timeout(timeout, async {
+        if tls {
+            let config = tokio_rustls::rustls::ClientConfig::builder()
+                .with_root_certificates(tokio_rustls::rustls::RootCertStore {
+                    roots: webpki_roots::TLS_SERVER_ROOTS.into()
+                })
+                .with_no_client_auth();
+            let server_name =
+                tokio_rustls::rustls::pki_types::ServerName::try_from(host)?;
+            let mut stream = tokio_rustls::TlsConnector::from(Arc::new(config))
+                .connect(server_name, tcp_stream).await?;
+            stream.write_all(request.as_bytes()).await?;
+            stream.read_to_end(&mut response).await?;
+        } else {
+            let mut stream = tcp_stream;
+            stream.write_all(request.as_bytes()).await?;
+            stream.read_to_end(&mut response).await?;
+        }
+
+        Ok::<(), Error>(())
+    }).await
+        .with_context(|| "http request timed out")??;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n")
+        .ok_or(anyhow!("http response without body"))?;
+
+    Ok((head.to_owned(), body.to_owned()))
+}
+
+// nip-11 relay information document, following redirects
+async fn relay_information_get(
+    relay: &str, isolation: &str
+) -> Result<RelayInformationDocument, Error> {
+    let timeout = timeout_get();
+
+    let mut url = Url::parse(relay)
+        .with_context(|| format!("parsing relay {relay}"))?;
+    let mode = proxy_transport_get(isolation)?.mode_get(&url);
+    let scheme = match url.scheme() {
+        "wss" => "https",
+        "ws" => "http",
+        scheme => return Err(anyhow!("{scheme} is not a relay scheme"))
+    };
+    url.set_scheme(scheme)
+        .map_err(|_| anyhow!("setting http scheme"))?;
+
+    for _ in 0..5 {
+        let (head, body) = http_get(
+            &url, &mode, "application/nostr+json", timeout
+        ).await?;
+
+        let status = head.lines().next().unwrap_or_default();
+        match status.split_whitespace().nth(1) {
+            Some("200") => return RelayInformationDocument::from_json(&body)
+                .with_context(|| "parsing relay information"),
+            Some("301"|"302"|"303"|"307"|"308") => {
+                let location = head.lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| {
+                        name.trim().eq_ignore_ascii_case("location")
+                    })
+                    .ok_or(anyhow!("http redirect without location"))?
+                    .1
+                    .trim();
+                url = url.join(location)
+                    .with_context(|| format!("parsing location {location}"))?;
+            },
+            _ => return Err(anyhow!("http response {status}"))
+        }
+    }
+
+    Err(anyhow!("too many http redirects"))
+}
+
+// check that a socks5 proxy is listening on the socket
+async fn socks_proxy_check(proxy: &str) -> Result<(), Error> {
+    let mut stream = tokio::time::timeout(
+        timeout_get(), tokio::net::TcpStream::connect(proxy)
+    ).await
+        .with_context(|| "connecting to proxy timed out")?
+        .with_context(|| "connecting to proxy")?;
+
+    // version 5, 2 authentication methods, no authentication and
+    // username/password
+    stream.write_all(&[5, 2, 0, 2]).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await
+        .with_context(|| "reading proxy reply")?;
+
+    if reply[0] != 5 || (reply[1] != 0 && reply[1] != 2) {
+        return Err(anyhow!("proxy is not socks5"));
+    }
+
+    Ok(())
+}
+
+// actions
+
+fn keys_format_get(
+    keys: &Keys, key_type_format: &KeyTypeFormat, options: &KeyOptions
+) -> Result<Zeroizing<String>, Error> {
+    Ok(Zeroizing::new(match key_type_format {
+        KeyTypeFormat::SecretHex => keys.secret_key().to_secret_hex(),
+        KeyTypeFormat::SecretBech32 => keys.secret_key().to_bech32()?,
+        KeyTypeFormat::PublicHex => keys.public_key().to_hex(),
+        KeyTypeFormat::PublicBech32 => keys.public_key().to_bech32()?,
+        KeyTypeFormat::Ncryptsec => {
+            nip49::EncryptedSecretKey::new(
+                keys.secret_key(),
+                &password_read("new password: ", true)?,
+                options.log_n,
+                options.key_security
+            )?.to_bech32()?
+        },
+        KeyTypeFormat::Mnemonic =>
+            return Err(anyhow!("can not get mnemonic from key"))
+    }))
+}
+
+fn key_convert(
+    key: &str, key_type_format: KeyTypeFormat, options: KeyOptions
+) -> Result<(), Error> {
+    secret_output_check(&key_type_format)?;
+
+    let key_output = if let Ok(keys) = Keys::parse(key) {
+        keys_format_get(&keys, &key_type_format, &options)?
+    } else if let Ok(public_key) = PublicKey::parse(key) {
+        match key_type_format {
+            KeyTypeFormat::PublicHex => Zeroizing::new(public_key.to_hex()),
+            KeyTypeFormat::PublicBech32 =>
+                Zeroizing::new(public_key.to_bech32()?),
+            _ => return Err(anyhow!("can not get private key from public key"))
+        }
+    } else {
+        return Err(anyhow!("stdin is not a secret key nor a public key"));
+    };
+
+    println!("{}", *key_output);
+
+    Ok(())
+}
+
+// new random key, or new mnemonic (nip-06)
+fn key_generate(
+    key_type_format: KeyTypeFormat, options: KeyOptions
+) -> Result<(), Error> {
+    secret_output_check(&key_type_format)?;
+
+    let key_output = match key_type_format {
+        KeyTypeFormat::PublicHex|KeyTypeFormat::PublicBech32 => {
+            return Err(anyhow!("can not generate only a public key"));
+        },
+        KeyTypeFormat::Mnemonic => {
+            if ! [12, 15, 18, 21, 24].contains(&options.words) {
+                return Err(anyhow!("words should be 12, 15, 18, 21 or 24"));
+            }
+            let entropy: Zeroizing<[u8; 32]> = Zeroizing::new(rand::random());
+            let mnemonic = Zeroizing::new(
+                nostr_sdk::nostr::bip39::Mnemonic::from_entropy(
+                    &entropy[..options.words / 3 * 4]
+                )?.to_string()
+            );
+            // npub at the account, to check the new identity
+            let keys = mnemonic_keys_get(&mnemonic, &options)?;
+            Zeroizing::new(format!(
+                "{}\n{}", *mnemonic, keys.public_key().to_bech32()?
+            ))
+        },
+        _ => keys_format_get(&Keys::generate(), &key_type_format, &options)?
+    };
+
+    println!("{}", *key_output);
+
+    Ok(())
+}
+
+// npub prefix of a vanity key, checking that prefix and suffix can be in an
+// npub
+fn vanity_prefix_get(prefix: &str, suffix: &str) -> Result<String, Error> {
+    let prefix = prefix.strip_prefix("npub1").unwrap_or(prefix);
+    for c in prefix.chars().chain(suffix.chars()) {
+        if ! "qpzry9x8gf2tvdw0s3jn54khce6mua7l".contains(c) {
+            return Err(anyhow!(
+                "{c} is not in the bech32 alphabet qpzry9x8gf2tvdw0s3jn54khce6mua7l"
+            ));
+        }
+    }
+    // npub1 followed by 58 characters
+    if prefix.len() + suffix.len() > 58 {
+        return Err(anyhow!("prefix and suffix longer than an npub"));
+    }
+    // the 52nd character, before the 6 of the checksum, has 1 bit of the key
+    // and 4 of padding, so it can only be q or s
+    let last_data = prefix.chars().nth(51)
+        .or_else(|| suffix.chars().rev().nth(6));
+    if let Some(c) = last_data && ! ['q', 's'].contains(&c) {
+        return Err(anyhow!(
+            "{c} can not be the 7th character from the end of an npub, only q \
+             or s"
+        ));
+    }
+
+    Ok(format!("npub1{prefix}"))
+}
+
+// new key with the npub starting with prefix and ending with suffix, using
+// all the cpu cores, progress and estimated time on stderr
+fn key_vanity(
+    key_type_format: KeyTypeFormat, prefix: &str, suffix: &str,
+    options: KeyOptions
+) -> Result<(), Error> {
+    if matches!(
+        key_type_format,
+        KeyTypeFormat::PublicHex|KeyTypeFormat::PublicBech32|
+        KeyTypeFormat::Mnemonic
+    ) {
+        return Err(anyhow!("key format should be of a private key"));
+    }
+    secret_output_check(&key_type_format)?;
+
+    let prefix = vanity_prefix_get(prefix, suffix)?;
+
+    let threads = std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1);
+
+    let expected = 32f64.powi((prefix.len() - 5 + suffix.len()) as i32);
+    let tried = std::sync::atomic::AtomicU64::new(0);
+    let keys_found: Mutex<Option<Keys>> = Mutex::new(None);
+    let found = AtomicBool::new(false);
+
+    let stderr_terminal = std::io::stderr().is_terminal();
+    let start = std::time::Instant::now();
+
+    let panicked = std::thread::scope(|scope| {
+        let mut workers = Vec::new();
+        for _ in 0..threads {
+            let prefix = &prefix;
+            let tried = &tried;
+            let keys_found = &keys_found;
+            let found = &found;
+
+            workers.push(scope.spawn(move || {
+                // counted in batches, not to share the counter every key
+                let mut tried_batch = 0;
+                while ! found.load(Ordering::Relaxed) {
+                    let keys = Keys::generate();
+                    tried_batch += 1;
+                    if tried_batch == 1024 {
+                        tried.fetch_add(tried_batch, Ordering::Relaxed);
+                        tried_batch = 0;
+                    }
+
+                    let Ok(npub) = keys.public_key().to_bech32();
+                    if npub.starts_with(prefix.as_str()) && npub.ends_with(suffix) {
+                        if ! found.swap(true, Ordering::Relaxed)
+                            && let Ok(mut keys_found) = keys_found.lock() {
+                            *keys_found = Some(keys);
+                        }
+                        break;
+                    }
+                }
+                tried.fetch_add(tried_batch, Ordering::Relaxed);
+            }));
+        }
+
+        // the workers stop when the key is found, or if they panic
+        let mut seconds: u64 = 0;
+        while ! found.load(Ordering::Relaxed)
+            && workers.iter().any(|worker| ! worker.is_finished()) {
+            std::thread::sleep(Duration::from_millis(100));
+            if start.elapsed().as_secs() == seconds {
+                continue;
+            }
+            seconds = start.elapsed().as_secs();
+
+            // progress every second in a terminal, every minute otherwise
+            if ! stderr_terminal && ! seconds.is_multiple_of(60) {
+                continue;
+            }
+
+            let tried = tried.load(Ordering::Relaxed);
+            let rate = tried as f64 / start.elapsed().as_secs_f64();
+            let eta = ((expected - tried as f64) / rate).max(0.0) as u64;
+            eprint!(
+                "{}{tried} keys tried, {rate:.0} keys/s, expected {expected:.0}, \
+                 eta {}h {:02}m {:02}s{}",
+                if stderr_terminal { "\r\x1b[K" } else { "" },
+                eta / 3600, eta / 60 % 60, eta % 60,
+                if stderr_terminal { "" } else { "\n" }
+            );
+        }
+        if stderr_terminal && seconds > 0 {
+            eprintln!();
+        }
+
+        // joined so that a panic is an error rather than a panic of the scope
+        workers.into_iter()
+            .map(|worker| worker.join())
+            .filter(Result::is_err)
+            .count()
+    });
+
+    let keys = keys_found.into_inner()
+        .map_err(|_| anyhow!("vanity threads poisoned"))?
+        .ok_or(anyhow!("vanity key not found, {panicked} threads panicked"))?;
+
+    println!("{}", *keys_format_get(&keys, &key_type_format, &options)?);
+
+    Ok(())
+}
+
+// keys of a mnemonic (nip-06), at account and with the bip-39 passphrase
+fn mnemonic_keys_get(
+    mnemonic: &str, options: &KeyOptions
+) -> Result<Keys, Error> {
+    let passphrase = if options.passphrase {
+        Some(password_read("passphrase: ", false)?)
+    } else {
+        None
+    };
+
+    Keys::from_mnemonic_with_account(
+        mnemonic,
+        passphrase.as_deref().map(String::as_str),
+        Some(options.account)
+    ).with_context(|| "deriving key from mnemonic")
+}
+
+// key of a mnemonic (nip-06), at account and with the bip-39 passphrase
+fn key_from_mnemonic(
+    mnemonic: &str, key_type_format: KeyTypeFormat, options: KeyOptions
+) -> Result<(), Error> {
+    secret_output_check(&key_type_format)?;
+
+    let keys = mnemonic_keys_get(mnemonic, &options)?;
+
+    println!("{}", *keys_format_get(&keys, &key_type_format, &options)?);
+
+    Ok(())
+}
+
+fn nip44_encrypt(
+    private_key: &str, public_key: &str, plaintext: &str,
+    payload_format: PayloadFormat
+) -> Result<(), Error> {
+    println!(
+        "{}",
+        nip44_payload_get(private_key, public_key, plaintext, payload_format)?
+    );
+
+    Ok(())
+}
+
+fn nip44_decrypt(
+    private_key: &str, public_key: &str, payload: &str,
+    payload_format: PayloadFormat
+) -> Result<(), Error> {
+    println!(
+        "{}",
+        nip44_plaintext_get(private_key, public_key, payload, payload_format)?
+    );
+
+    Ok(())
+}
+
+fn nip44_payload_get(
+    private_key: &str, public_key: &str, plaintext: &str,
+    payload_format: PayloadFormat
+) -> Result<String, Error> {
+    use nostr_sdk::nostr::base64::Engine;
+
+    let keys = Keys::parse(private_key)?;
+    let public_key = PublicKey::parse(public_key)?;
+
+    let plaintext: Vec<u8> = match payload_format {
+        PayloadFormat::Raw => plaintext.as_bytes().to_vec(),
+        PayloadFormat::Base64 => nostr_sdk::nostr::base64::engine::general_purpose
+            ::STANDARD.decode(plaintext)
+            .with_context(|| "decoding base64 plaintext")?
+    };
+
+    Ok(nip44::encrypt(
+        keys.secret_key(), &public_key, plaintext, nip44::Version::V2
+    )?)
+}
+
+fn nip44_plaintext_get(
+    private_key: &str, public_key: &str, payload: &str,
+    payload_format: PayloadFormat
+) -> Result<String, Error> {
+    use nostr_sdk::nostr::base64::Engine;
+
+    let keys = Keys::parse(private_key)?;
+    let public_key = PublicKey::parse(public_key)?;
+
+    let plaintext = nip44::decrypt_to_bytes(
+        keys.secret_key(), &public_key, payload
+    ).with_context(|| "decrypting payload")?;
+
+    Ok(match payload_format {
+        PayloadFormat::Raw => String::from_utf8(plaintext)
+            .with_context(|| "plaintext is not utf-8, use base64")?,
+        PayloadFormat::Base64 =>
+            nostr_sdk::nostr::base64::engine::general_purpose::STANDARD
+                .encode(plaintext)
+    })
+}
+
+// add a nip-13 nonce tag to the event until its id has difficulty leading
+// zero bits, using all the cpu cores
+fn unsigned_event_pow(
+    mut event: UnsignedEvent, difficulty: u8
+) -> Result<UnsignedEvent, Error> {
+    if difficulty == 0 {
+        return Ok(event);
+    }
+
+    let threads = std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(1) as u128;
+
+    let found = AtomicBool::new(false);
+    let nonce_found: Mutex<Option<u128>> = Mutex::new(None);
+
+    std::thread::scope(|scope| {
+        for thread in 0..threads {
+            let event = &event;
+            let found = &found;
+            let nonce_found = &nonce_found;
+
+            scope.spawn(move || {
+                let mut nonce = thread;
+                let mut tags = event.tags.clone();
+                tags.push(Tag::pow(nonce, difficulty));
+
+                while ! found.load(Ordering::Relaxed) {
+                    let id = EventId::new(
+                        &event.pubkey, &event.created_at, &event.kind,
+                        &tags, &event.content
+                    );
+                    if nip13::get_leading_zero_bits(id.as_bytes()) >= difficulty {
+                        if ! found.swap(true, Ordering::Relaxed)
+                            && let Ok(mut nonce_found) = nonce_found.lock() {
+                            *nonce_found = Some(nonce);
+                        }
+                        return;
+                    }
+
+                    nonce += threads;
+                    tags.pop();
+                    tags.push(Tag::pow(nonce, difficulty));
+                }
+            });
+        }
+    });
+
+    let nonce = nonce_found.into_inner()
+        .map_err(|_| anyhow!("pow threads poisoned"))?
+        .ok_or(anyhow!("pow nonce not found"))?;
+
+    event.tags.push(Tag::pow(nonce, difficulty));
+    event.id = None;
+    event.ensure_id();
+
+    Ok(event)
+}
+
+// nip-59 created_at of a seal or a gift wrap, random up to 2 days before the
+// rumor rather than now, so scheduled dms are not dated before being sent
+fn gift_wrap_created_at_get(rumor: &UnsignedEvent) -> Timestamp {
+    rumor.created_at - rand::Rng::gen_range(
+        &mut rand::thread_rng(), nip59::RANGE_RANDOM_TIMESTAMP_TWEAK
+    )
+}
+
+// nip-59 gift wrap, the outer event signed with a new ephemeral key is mined
+// with pow
+async fn gift_wrap_build(
+    signer: &Arc<dyn NostrSigner>, receiver: &PublicKey,
+    mut rumor: UnsignedEvent, extra_tags: Vec<Tag>, pow: u8
+) -> Result<(Event, Keys), Error> {
+    rumor.ensure_id();
+
+    let seal: Event = EventBuilder::new(
+        Kind::Seal,
+        signer.nip44_encrypt(receiver, &rumor.as_json()).await?
+    )
+        .custom_created_at(gift_wrap_created_at_get(&rumor))
+        .sign(signer).await?;
+
+    let ephemeral_keys = Keys::generate();
+    let content = nip44::encrypt(
+        ephemeral_keys.secret_key(),
+        receiver,
+        seal.as_json(),
+        nip44::Version::default()
+    )?;
+
+    let mut tags = extra_tags;
+    tags.push(Tag::public_key(*receiver));
+
+    let wrap: UnsignedEvent = EventBuilder::new(Kind::GiftWrap, content)
+        .tags(tags)
+        .custom_created_at(gift_wrap_created_at_get(&rumor))
+        .build(ephemeral_keys.public_key());
+
+    let wrap = unsigned_event_pow(wrap, pow)?.sign_with_keys(&ephemeral_keys)?;
+
+    Ok((wrap, ephemeral_keys))
+}
+
+// append the ephemeral keys of the gift wraps, encrypted to the signer
+async fn wrap_keys_write(
+    signer: &Arc<dyn NostrSigner>, wrap_keys: &str, wraps: &[(&Event, &Keys)]
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+
+    let mut content = String::new();
+    for (wrap, ephemeral_keys) in wraps {
+        content += &serde_json::to_string(&WrapKeyEntry {
+            id: wrap.id,
+            pubkey: ephemeral_keys.public_key(),
+            key: signer.nip44_encrypt(
+                &public_key,
+                &ephemeral_keys.secret_key().to_secret_hex()
+            ).await?,
+            relays: Vec::new()
+        })?;
+        content += "\n";
+    }
+
+    file_append(wrap_keys, &content)
+        .with_context(|| "saving wrap keys")
+}
+
+// add the relays gift wraps are sent to, to their entries in the wrap keys
+// file
+fn wrap_keys_relays_write(
+    wrap_keys: &str, events_relays: &[(&Event, &Vec<String>)]
+) -> Result<(), Error> {
+    let mut content = String::new();
+    for entry in serde_json::Deserializer::from_str(
+        &file_read(wrap_keys).with_context(|| "reading wrap keys")?
+    ).into_iter::<WrapKeyEntry>() {
+        let mut entry = entry.with_context(|| "deserializing wrap key")?;
+
+        for (event, relays) in events_relays {
+            if event.id != entry.id {
+                continue;
+            }
+            for relay in relays_normalize(relays)? {
+                if ! entry.relays.contains(&relay) {
+                    entry.relays.push(relay);
+                }
+            }
+        }
+
+        content += &serde_json::to_string(&entry)?;
+        content += "\n";
+    }
+
+    file_write(wrap_keys, &content)
+        .with_context(|| "saving wrap keys")
+}
+
+fn unsigned_event_json_get(
+    event: UnsignedEvent,
+    extra_fields: Option<JsonOrdered>
+) -> Result<JsonOrdered, Error> {
+    let mut event_json = JsonOrdered::new();
+
+    event_json.insert(
+        format!("id"),
+        serde_json::json!(event.id)
+    );
+
+    event_json.insert(
+        format!("pubkey"),
+        serde_json::json!({
+            "bech32": event.pubkey.to_bech32()?,
+            "hex": event.pubkey.to_hex()
+        })
+    );
+
+    event_json.insert(
+        format!("created_at"),
+        serde_json::json!({
+            "timestamp": event.created_at.as_u64(),
+            "date": serde_json::json!(
+                unix_timestamp_s_to_string(event.created_at.as_u64())?
+            )
+        })
+    );
+
+    event_json.insert(
+        format!("kind"),
+        serde_json::json!(event.kind)
+    );
+
+    event_json.insert(
+        format!("tags"),
+        serde_json::json!(event.tags.as_slice())
+    );
+
+    event_json.insert(
+        format!("content"),
+        serde_json::from_str::<serde_json::Value>(&event.content)
+            .unwrap_or_else(|_| serde_json::json!(&event.content))
+    );
+
+    if let Some(extra_fields) = extra_fields {
+        event_json.extend(extra_fields);
+    }
+
+    Ok(event_json)
+}
+
+fn unsigned_event_print(
+    event: UnsignedEvent,
+    extra_fields: Option<JsonOrdered>
+) -> Result<(), Error> {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(
+            &unsigned_event_json_get(event, extra_fields)?
+        )?
+    );
+
+    Ok(())
+}
+
+// isolation of the connections of an identity, the public keys used, a new
+// circuit is forced adding a random nonce
+fn isolation_get(public_keys: &[PublicKey], new_circuit: bool) -> String {
+    let mut public_keys_hex: Vec<String> = public_keys.iter()
+        .map(|public_key| public_key.to_hex())
+        .collect();
+    public_keys_hex.sort();
+    public_keys_hex.dedup();
+
+    let mut isolation = public_keys_hex.join(",");
+    if new_circuit {
+        isolation.push_str(&format!(":{:032x}", rand::random::<u128>()));
+    }
+
+    isolation
+}
+
+// create client with the proxy transport, connect it to the relays and
+// return it, connections are isolated per relay and per isolation
+async fn client_connected_relays_get(
+    relays_list: &Vec<Vec<String>>, isolation: &str
+) -> Result<Client, Error> {
+    let timeout = timeout_get();
+
+    let client = Client::builder()
+        .websocket_transport(proxy_transport_get(isolation)?)
+        .opts(ClientOptions::new()
+            .gossip(false)
+        ).build();
+
+    for relays in relays_list {
+        for relay in relays {
+            client.add_relay(relay).await?;
+        }
+    }
+    for (key, value) in client.try_connect(timeout).await.failed {
+        eprintln!("error: {key} connecting {value}");
+    }
+
+    Ok(client)
+}
+
+async fn events_fetch_filter(
+    filter: Filter, relays: Vec<String>, isolation: &str
+) -> Result<Events, Error> {
+    let timeout = timeout_get();
+
+    let client = client_connected_relays_get(&vec![relays], isolation).await?;
+
+    let events: Events = client
+        .fetch_events(filter, timeout)
+        .await?;
+
+    client.disconnect().await;
+
+    Ok(events)
+}
+
+// relays that accepted an event, rejected it with the ok message, timed out
+// waiting for the ok message and were unreachable, not connected before
+// sending or failing without an ok message
+#[derive(Default)]
+struct EventSendOutput {
+    accepted: Vec<String>,
+    rejected: JsonOrdered,
+    timeout: Vec<String>,
+    unreachable: Vec<String>
+}
+
+// send the event to every connected relay, the others are unreachable
+async fn event_send_to<U: AsRef<str>>(
+    client: &Client, relays: &[U], event: &Event
+) -> Result<EventSendOutput, Error> {
+    let mut output = EventSendOutput::default();
+
+    let mut sending = tokio::task::JoinSet::new();
+    for relay in relays {
+        let relay = client.relay(relay.as_ref()).await?;
+        if ! relay.is_connected() {
+            output.unreachable.push(relay.url().to_string());
+            continue;
+        }
+
+        let event = event.clone();
+        sending.spawn(async move {
+            (relay.url().to_string(), relay.send_event(&event).await)
+        });
+    }
+
+    while let Some(sent) = sending.join_next().await {
+        match sent? {
+            (relay, Ok(_)) => output.accepted.push(relay),
+            (relay, Err(RelayError::RelayMessage(message))) => {
+                output.rejected.insert(relay, serde_json::json!(message));
+            },
+            (relay, Err(RelayError::Timeout)) =>
+                output.timeout.push(relay),
+            (relay, Err(_)) => output.unreachable.push(relay)
+        }
+    }
+
+    output.accepted.sort();
+    output.rejected.sort_keys();
+    output.timeout.sort();
+    output.unreachable.sort();
+
+    Ok(output)
+}
+
+fn events_send_report_get(
+    index: usize, id: &EventId, output: &EventSendOutput
+) -> JsonOrdered {
+    let mut report = JsonOrdered::new();
+    report.insert(format!("event"), serde_json::json!(index + 1));
+    report.insert(format!("id"), serde_json::json!(id));
+    report.insert(format!("accepted"), serde_json::json!(output.accepted));
+    report.insert(format!("rejected"), serde_json::json!(output.rejected));
+    report.insert(format!("timeout"), serde_json::json!(output.timeout));
+    report.insert(
+        format!("unreachable"), serde_json::json!(output.unreachable)
+    );
+
+    report
+}
+
+// sent is called with the index of every event accepted by min_success (at
+// least 1) relays or written in the outbox
+async fn events_send(
+    events: Vec<Event>, relays_list: Vec<Vec<String>>,
+    options: EventsSendOptions,
+    mut sent: impl FnMut(usize) -> Result<(), Error>
+) -> Result<(), Error> {
+    if relays_list.len() != 1 && relays_list.len() != events.len() {
+        return Err(anyhow!(
+            "relays list should be len 1 or the same \
+             as the len of the events"
+        ));
+    }
+
+    // an event that can never reach min_success would stay in the outbox
+    if let Some(relays) = relays_list.iter()
+        .find(|relays| relays.len() < options.min_success) {
+        return Err(anyhow!(
+            "min_success {} larger than the {} relays {}",
+            options.min_success, relays.len(), relays.join(" ")
+        ));
+    }
+
+    // gift wraps to self_public_key, the self copies of the dms, are sent
+    // after the other events
+    let is_self_copy = |event: &Event| {
+        options.self_public_key.is_some_and(|self_public_key|
+            event.kind == Kind::GiftWrap &&
+            event.tags.public_keys().any(|public_key|
+                *public_key == self_public_key
+            )
+        )
+    };
+    let mut order: Vec<usize> = (0..events.len())
+        .filter(|i| ! is_self_copy(&events[*i]))
+        .collect();
+    let self_copies_start = order.len();
+    order.extend((0..events.len()).filter(|i| is_self_copy(&events[*i])));
+
+    let relays_get = |i: usize| if relays_list.len() == 1 {
+        &relays_list[0]
+    } else {
+        &relays_list[i]
+    };
+
+    let identities: Vec<PublicKey> = events.iter()
+        .map(|event| event_identity_get(event, &options))
+        .collect();
+
+    // recorded before sending, a deletion to a relay that did not get the
+    // gift wrap does no harm
+    if let Some(wrap_keys) = &options.wrap_keys {
+        wrap_keys_relays_write(wrap_keys, &events.iter()
+            .enumerate()
+            .map(|(i, event)| (event, relays_get(i)))
+            .collect::<Vec<_>>()
+        )?;
+    }
+
+    let clients = if options.separate {
+        HashMap::new()
+    } else {
+        clients_per_identity_get(
+            &(0..events.len())
+                .map(|i| (identities[i], relays_get(i)))
+                .collect::<Vec<_>>(),
+            options.new_circuit
+        ).await?
+    };
+
+    let mut events_not_delivered: usize = 0;
+
+    for (n, i) in order.into_iter().enumerate() {
+        let relays = relays_get(i);
+
+        if n > 0 && options.delay > 0 {
+            tokio::time::sleep(Duration::from_secs(rand::Rng::gen_range(
+                &mut rand::thread_rng(), 0..=options.delay
+            ))).await;
+        }
+        if n == self_copies_start && options.self_delay > 0 {
+            tokio::time::sleep(Duration::from_secs(options.self_delay)).await;
+        }
+
+        // every event on its own connections and circuits
+        let client_separate = if options.separate {
+            Some(client_connected_relays_get(
+                &vec![relays.clone()],
+                &isolation_get(&[identities[i]], true)
+            ).await?)
+        } else {
+            None
+        };
+        let client = client_separate.as_ref()
+            .or(clients.get(&identities[i]))
+            .ok_or(anyhow!("client not created"))?;
+
+        let output = event_send_to(client, relays, &events[i]).await?;
+
+        if let Some(client) = client_separate {
+            client.disconnect().await;
+        }
+
+        if output.accepted.len() < options.min_success {
+            events_not_delivered += 1;
+        }
+
+        let min_success = options.min_success.max(1);
+        if output.accepted.len() >= min_success {
+            sent(i)?;
+        } else if let Some(outbox) = &options.outbox {
+            outbox_entry_write(outbox, &OutboxEntry {
+                event: events[i].clone(),
+                relays: relays_normalize(relays)?,
+                accepted: output.accepted.clone(),
+                min_success,
+                attempts: 1,
+                identity: Some(identities[i])
+            })?;
+            sent(i)?;
+        }
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(
+                &events_send_report_get(i, &events[i].id, &output)
+            )?
+        );
+    }
+
+    for client in clients.values() {
+        client.disconnect().await;
+    }
+
+    if events_not_delivered > 0 {
+        return Err(anyhow!(
+            "{events_not_delivered} events accepted by less than {} relays",
+            options.min_success
+        ));
+    }
+
+    Ok(())
+}
+
+// identity whose connections and circuits are used to send an event, the
+// identity option, or self_public_key for gift wraps as they are signed with
+// one-time keys, otherwise the author
+fn event_identity_get(
+    event: &Event, options: &EventsSendOptions
+) -> PublicKey {
+    options.identity
+        .or(options.self_public_key.filter(|_| event.kind == Kind::GiftWrap))
+        .unwrap_or(event.pubkey)
+}
+
+// one client per identity connected to the relays of its events, so
+// different identities never share connections and circuits
+async fn clients_per_identity_get(
+    identities_relays: &[(PublicKey, &Vec<String>)], new_circuit: bool
+) -> Result<HashMap<PublicKey, Client>, Error> {
+    let mut relays_lists: HashMap<PublicKey, Vec<Vec<String>>> =
+        HashMap::new();
+    for (identity, relays) in identities_relays {
+        relays_lists.entry(*identity)
+            .or_default()
+            .push(relays.to_vec());
+    }
+
+    let mut clients: HashMap<PublicKey, Client> = HashMap::new();
+    for (identity, relays_list) in relays_lists {
+        clients.insert(identity, client_connected_relays_get(
+            &relays_list, &isolation_get(&[identity], new_circuit)
+        ).await?);
+    }
+
+    Ok(clients)
+}
+
+fn relays_normalize(relays: &[String]) -> Result<Vec<String>, Error> {
+    let mut relays_normalized: Vec<String> = Vec::new();
+
+    for relay in relays {
+        relays_normalized.push(RelayUrl::parse(relay)
+            .with_context(|| format!("parsing relay {relay}"))?
+            .to_string()
+        );
+    }
+
+    Ok(relays_normalized)
+}
+
+fn outbox_entry_write(outbox: &str, entry: &OutboxEntry) -> Result<(), Error> {
+    if ! path_exists(outbox) {
+        mkdir(outbox)
+            .with_context(|| "creating outbox directory")?;
+    }
+
+    file_write(
+        &format!("{outbox}/{}", entry.event.id),
+        &(serde_json::to_string_pretty(entry)? + "\n")
+    ).with_context(|| "writing outbox entry")
+}
+
+// resend the events in the outbox to the relays that did not accept them yet,
+// waiting backoff seconds before the first retry and doubling it every retry,
+// removing the events accepted by min_success relays
+async fn outbox_flush(
+    outbox: &str, retries: u64, backoff: u64
+) -> Result<(), Error> {
+    if ! path_exists(outbox) {
+        return Ok(());
+    }
+
+    for retry in 0..=retries {
+        let entries_files = dir_files_get(outbox)?;
+        if entries_files.is_empty() {
+            return Ok(());
+        }
+
+        if retry > 0 {
+            tokio::time::sleep(Duration::from_secs(
+                backoff.saturating_mul(1 << (retry - 1).min(32))
+            )).await;
+        }
+
+        // a bad entry, or one with no relay left to try, is reported and
+        // left in the outbox, the others are still sent
+        let mut entries: Vec<(String, OutboxEntry)> = Vec::new();
+        for entry_file in entries_files {
+            let entry = match file_read(&entry_file).and_then(|entry| {
+                serde_json::from_str::<OutboxEntry>(&entry)
+                    .map_err(Error::from)
+            }) {
+                Ok(entry) => entry,
+                Err(error) => {
+                    eprintln!("outbox entry {entry_file} skipped: {error:#}");
+                    continue;
+                }
+            };
+
+            if entry.relays.iter()
+                .all(|relay| entry.accepted.contains(relay)) {
+                eprintln!(
+                    "outbox entry {entry_file} skipped: accepted by every \
+                     relay, min_success {} not reachable",
+                    entry.min_success
+                );
+                continue;
+            }
+
+            entries.push((entry_file, entry));
+        }
+        if entries.is_empty() {
+            break;
+        }
+
+        let clients = clients_per_identity_get(
+            &entries.iter()
+                .map(|(_, entry)| (
+                    entry.identity.unwrap_or(entry.event.pubkey),
+                    &entry.relays
+                ))
+                .collect::<Vec<_>>(),
+            false
+        ).await?;
+
+        for (i, (entry_file, mut entry)) in entries.into_iter().enumerate() {
+            let client = clients.get(
+                &entry.identity.unwrap_or(entry.event.pubkey)
+            ).ok_or(anyhow!("client not created"))?;
+
+            let relays_pending: Vec<&String> = entry.relays.iter()
+                .filter(|relay| ! entry.accepted.contains(relay))
+                .collect();
+
+            let output = match event_send_to(
+                client, &relays_pending, &entry.event
+            ).await {
+                Ok(output) => output,
+                Err(error) => {
+                    eprintln!("outbox entry {entry_file} not sent: {error}");
+                    continue;
+                }
+            };
+
+            println!(
+                "{}",
+                serde_json::to_string_pretty(
+                    &events_send_report_get(i, &entry.event.id, &output)
+                )?
+            );
+
+            entry.accepted.extend(output.accepted);
+            entry.attempts += 1;
+
+            if entry.accepted.len() >= entry.min_success {
+                file_remove(&entry_file)
+                    .with_context(|| "removing outbox entry")?;
+            } else {
+                outbox_entry_write(outbox, &entry)?;
+            }
+        }
+
+        for client in clients.values() {
+            client.disconnect().await;
+        }
+    }
+
+    let entries_left = dir_files_get(outbox)?.len();
+    if entries_left > 0 {
+        return Err(anyhow!("{entries_left} events still in the outbox"));
+    }
+
+    Ok(())
+}
+
+fn schedule(
+    events: Vec<Event>, dir: &str, send_at: u64,
+    relays_list: Vec<Vec<String>>
+) -> Result<(), Error> {
+    if relays_list.len() != 1 && relays_list.len() != events.len() {
+        return Err(anyhow!(
+            "relays list should be len 1 or the same \
+             as the len of the events"
+        ));
+    }
+
+    if ! path_exists(dir) {
+        mkdir(dir)
+            .with_context(|| "creating schedule directory")?;
+    }
+
+    for (i, event) in events.into_iter().enumerate() {
+        let relays = if relays_list.len() == 1 {
+            &relays_list[0]
+        } else {
+            &relays_list[i]
+        };
+
+        let entry = ScheduleEntry {
+            send_at,
+            event,
+            relays: relays_normalize(relays)?
+        };
+
+        file_write(
+            &format!("{dir}/{send_at}-{}", entry.event.id),
+            &(serde_json::to_string_pretty(&entry)? + "\n")
+        ).with_context(|| "writing schedule entry")?;
+
+        println!(
+            "event {} scheduled at {}",
+            i + 1, unix_timestamp_s_to_string(send_at)?
+        );
+    }
+
+    Ok(())
+}
+
+// send the scheduled events that are due, removing them from the schedule,
+// events not accepted are moved to the outbox if one is in the options,
+// otherwise they stay in the schedule
+async fn schedule_run(
+    dir: &str, options: EventsSendOptions
+) -> Result<(), Error> {
+    if ! path_exists(dir) {
+        return Ok(());
+    }
+
+    let now = Timestamp::now().as_u64();
+
+    // a bad entry is reported and left in the schedule, the others are still
+    // sent
+    let mut entries_skipped: usize = 0;
+    let mut entries_files: Vec<String> = Vec::new();
+    let mut events: Vec<Event> = Vec::new();
+    let mut relays_list: Vec<Vec<String>> = Vec::new();
+    for entry_file in dir_files_get(dir)? {
+        let entry = match file_read(&entry_file).and_then(|entry| {
+            serde_json::from_str::<ScheduleEntry>(&entry)
+                .map_err(Error::from)
+        }) {
+            Ok(entry) => entry,
+            Err(error) => {
+                eprintln!("schedule entry {entry_file} skipped: {error:#}");
+                entries_skipped += 1;
+                continue;
+            }
+        };
+
+        if entry.send_at > now {
+            continue;
+        }
+
+        if entry.relays.len() < options.min_success {
+            eprintln!(
+                "schedule entry {entry_file} skipped: min_success {} larger \
+                 than its {} relays",
+                options.min_success, entry.relays.len()
+            );
+            entries_skipped += 1;
+            continue;
+        }
+
+        entries_files.push(entry_file);
+        events.push(entry.event);
+        relays_list.push(entry.relays);
+    }
+
+    // an entry is removed only once its event is sent or in the outbox, the
+    // others are tried again by the next schedule-run
+    if ! events.is_empty() {
+        events_send(events, relays_list, options, |i| {
+            file_remove(&entries_files[i])
+                .with_context(|| "removing schedule entry")
+        }).await?;
+    }
+
+    if entries_skipped > 0 {
+        return Err(anyhow!("{entries_skipped} schedule entries skipped"));
+    }
+
+    Ok(())
+}
+
+async fn metadata_event(
+    metadata: Metadata, signer: &Arc<dyn NostrSigner>, options: EventOptions
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+
+    let event = unsigned_event_pow(
+        EventBuilder::metadata(&metadata)
+            .build(public_key),
+        options.pow.difficulty_get(&public_key).await?
+    )?.sign(signer).await?;
+
+    println!("{}", event.as_pretty_json());
+
+    Ok(())
+}
+
+async fn events_sign(
+    signer: &Arc<dyn NostrSigner>, templates: Vec<EventTemplate>,
+    options: SignOptions
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+    let pow = options.pow.difficulty_get(&public_key).await?;
+
+    for template in templates {
+        let created_at = template.created_at
+            .or(options.created_at.map(Timestamp::from))
+            .unwrap_or_else(Timestamp::now);
+
+        let mut builder = EventBuilder::new(template.kind, template.content)
+            .tags(template.tags)
+            .custom_created_at(created_at);
+        if let Some(expiration) = options.expiration {
+            builder = builder.tag(Tag::expiration(
+                created_at + Duration::from_secs(expiration)
+            ));
+        }
+
+        let event = unsigned_event_pow(builder.build(public_key), pow)?
+            .sign(signer).await?;
+
+        println!("{}", event.as_pretty_json());
+    }
+
+    Ok(())
+}
+
+// currently specifying just read/write for nip-65 not supported
+async fn relay_list_event(
+    kind: Kind, signer: &Arc<dyn NostrSigner>, relays: Vec<String>,
+    options: EventOptions
+) -> Result<(), Error> {
+    let tag_kind = TagKind::Custom(std::borrow::Cow::Borrowed(match &kind {
+        Kind::RelayList => "r",
+        Kind::InboxRelays => "relay",
+        _ => return Err(anyhow!("wrong kind"))
+    }));
+
+    let mut builder = EventBuilder::new(kind, "");
+    for relay in relays {
+        builder = builder.tag(Tag::custom(tag_kind.clone(), [relay]));
+    }
+
+    let public_key = signer.get_public_key().await?;
+
+    let event = unsigned_event_pow(
+        builder.build(public_key),
+        options.pow.difficulty_get(&public_key).await?
+    )?.sign(signer).await?;
+
+    println!("{}", event.as_pretty_json());
+
+    Ok(())
+}
+
+fn filter_add_options(
+    mut filter: Filter, since: Option<u64>, until: Option<u64>
+) -> Filter {
+    if let Some(since) = since {
+        filter = filter.since(Timestamp::from(since))
+    }
+    if let Some(until) = until {
+        filter = filter.until(Timestamp::from(until))
+    }
+
+    filter
+}
+
+async fn events_fetch(
+    kinds: Vec<Kind>, public_key: &str, relays: Vec<String>,
+    since: Option<u64>, until: Option<u64>
+) -> Result<(), Error> {
+    let mut filter: Filter = Filter::new()
+        .authors([PublicKey::parse(public_key)?])
+        .kinds(kinds);
+    filter = filter_add_options(filter, since, until);
+
+    // no identity is involved, every run gets new circuits so unrelated
+    // reads are not linked
+    let events = events_fetch_filter(
+        filter, relays, &isolation_get(&[], true)
+    ).await?;
+
+    for event in events.to_vec() {
+        println!("{}", event.as_pretty_json());
+    }
+
+    Ok(())
+}
+
+fn rumors_info<T>(
+    rumors: Vec<T>
+) -> Result<(), Error>
+where
+    T: Into<UnsignedEvent>
+{
+    for rumor in rumors {
+        unsigned_event_print(rumor.into(), None)?;
+    }
+
+    Ok(())
+}
+
+// errors in the tags of an event: malformed standard tags, nonce not
+// matching the id, missing tags required by known kinds
+fn event_tags_errors_get(event: &Event) -> Vec<String> {
+    let mut errors: Vec<String> = Vec::new();
+
+    let mut tag_names: Vec<String> = Vec::new();
+    for tag in event.tags.iter() {
+        let tag_name = tag.kind().to_string();
+        let value = tag.content().unwrap_or_default();
+
+        let valid = match tag_name.as_str() {
+            "e" => EventId::from_hex(value).is_ok(),
+            "p" => PublicKey::from_hex(value).is_ok(),
+            "a" => Coordinate::parse(value).is_ok(),
+            "k" => Kind::from_str(value).is_ok(),
+            "expiration" => value.parse::<u64>().is_ok(),
+            // the target difficulty is optional (nip-13)
+            "nonce" => match tag.as_slice().get(2) {
+                Some(target) => match target.parse::<u8>() {
+                    Ok(target) => {
+                        if nip13::get_leading_zero_bits(event.id.as_bytes())
+                            < target {
+                            errors.push(format!(
+                                "nonce target {target} not reached"
+                            ));
+                        }
+                        true
+                    },
+                    Err(_) => false
+                },
+                None => true
+            },
+            // r tags are relays only in relay lists, elsewhere they are any
+            // reference
+            "r" if event.kind == Kind::RelayList =>
+                RelayUrl::parse(value).is_ok(),
+            "relay" => RelayUrl::parse(value).is_ok()
+                || (event.kind == Kind::RequestToVanish && value == "ALL_RELAYS"),
+            _ => true
+        };
+        if ! valid {
+            errors.push(format!("malformed tag {}", tag.as_slice().join(",")));
+        }
+
+        if ! tag_names.contains(&tag_name) {
+            tag_names.push(tag_name);
+        }
+    }
+
+    let required: &[&[&str]] = match event.kind {
+        Kind::GiftWrap | Kind::PrivateDirectMessage => &[&["p"]],
+        Kind::EventDeletion => &[&["e", "a"]],
+        Kind::Reaction => &[&["e", "a"]],
+        Kind::RelayList => &[&["r"]],
+        Kind::InboxRelays | Kind::RequestToVanish => &[&["relay"]],
+        _ => &[]
+    };
+    for names in required {
+        if ! names.iter().any(|name| tag_names.iter().any(|tag| tag == name)) {
+            errors.push(format!(
+                "kind {} without {} tag", event.kind.as_u16(), names.join(" or ")
+            ));
+        }
+    }
+
+    if event.kind == Kind::GiftWrap && event.tags.public_keys().count() != 1 {
+        errors.push(format!("gift wrap without exactly one p tag"));
+    }
+
+    errors
+}
+
+// nip-59 layering of a gift wrap to the signer: seal signed and without
+// tags, rumor unsigned with the pubkey of the seal
+async fn gift_wrap_open(
+    signer: &Arc<dyn NostrSigner>, wrap: &Event
+) -> Result<GiftWrapLayers, Error> {
+    let mut errors: Vec<String> = Vec::new();
+
+    let seal: Event = Event::from_json(
+        signer.nip44_decrypt(&wrap.pubkey, &wrap.content).await
+            .with_context(|| "decrypting gift wrap")?
+    ).with_context(|| "parsing seal")?;
+
+    if seal.kind != Kind::Seal {
+        errors.push(format!("seal with kind {}", seal.kind.as_u16()));
+    }
+    if ! seal.verify_id() {
+        errors.push(format!("seal id not valid"));
+    }
+    if ! seal.verify_signature() {
+        errors.push(format!("seal signature not valid"));
+    }
+    if ! seal.tags.is_empty() {
+        errors.push(format!("seal with tags"));
+    }
+
+    let seal_errors = errors;
+    let mut errors: Vec<String> = Vec::new();
+
+    let rumor_plaintext = signer.nip44_decrypt(&seal.pubkey, &seal.content)
+        .await
+        .with_context(|| "decrypting seal")?;
+
+    let rumor_json: JsonOrdered = serde_json::from_str(&rumor_plaintext)
+        .with_context(|| "parsing rumor")?;
+
+    if rumor_json.contains_key("sig") {
+        errors.push(format!("rumor signed"));
+    }
+
+    let rumor: UnsignedEvent = serde_json::from_str(&rumor_plaintext)
+        .with_context(|| "parsing rumor")?;
+
+    if rumor.pubkey != seal.pubkey {
+        errors.push(format!("rumor pubkey different from seal pubkey"));
+    }
+    if let Some(id) = rumor.id {
+        let id_computed = EventId::new(
+            &rumor.pubkey, &rumor.created_at, &rumor.kind, &rumor.tags,
+            &rumor.content
+        );
+        if id != id_computed {
+            errors.push(format!("rumor id not valid"));
+        }
+    }
+
+    Ok(GiftWrapLayers {
+        seal,
+        seal_errors,
+        rumor: rumor_json,
+        rumor_errors: errors
+    })
+}
+
+// check id, signature and tags of the events, and the layering of the gift
+// wraps to the key, printing a report for every event
+async fn events_verify(
+    signer: Option<Arc<dyn NostrSigner>>, events: Vec<serde_json::Value>
+) -> Result<(), Error> {
+    let public_key = match &signer {
+        Some(signer) => Some(signer.get_public_key().await?),
+        None => None
+    };
+
+    let mut events_failed: usize = 0;
+
+    for (index, value) in events.into_iter().enumerate() {
+        let mut report = JsonOrdered::new();
+        report.insert(format!("event"), serde_json::json!(index + 1));
+
+        let mut errors: Vec<String> = Vec::new();
+
+        match serde_json::from_value::<Event>(value) {
+            Ok(event) => {
+                report.insert(format!("id"), serde_json::json!(event.id));
+                report.insert(format!("kind"), serde_json::json!(event.kind));
+
+                if ! event.verify_id() {
+                    errors.push(format!("id not valid"));
+                }
+                if ! event.verify_signature() {
+                    errors.push(format!("signature not valid"));
+                }
+                errors.extend(event_tags_errors_get(&event));
+
+                if let (Some(signer), Some(public_key)) = (&signer, &public_key)
+                    && event.kind == Kind::GiftWrap
+                    && event.tags.public_keys().any(|p| p == public_key) {
+                    match gift_wrap_open(signer, &event).await {
+                        Ok(layers) => {
+                            errors.extend(layers.seal_errors);
+                            errors.extend(layers.rumor_errors);
+                        },
+                        Err(error) => errors.push(format!("{error:#}"))
+                    }
+                }
+            },
+            Err(error) => errors.push(format!("parsing event: {error}"))
+        }
+
+        if ! errors.is_empty() {
+            events_failed += 1;
+        }
+
+        report.insert(format!("valid"), serde_json::json!(errors.is_empty()));
+        report.insert(format!("errors"), serde_json::json!(errors));
+
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
+    if events_failed > 0 {
+        return Err(anyhow!("{events_failed} events failed verification"));
+    }
+
+    Ok(())
+}
+
+// gift wrap (nip-59) every rumor to every receiver
+async fn gift_wrap(
+    signer: &Arc<dyn NostrSigner>, receivers: Vec<String>,
+    templates: Vec<EventTemplate>, options: DmEventsOptions
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+
+    let mut receivers_public_key: Vec<PublicKey> = Vec::new();
+    for receiver in &receivers {
+        receivers_public_key.push(PublicKey::parse(receiver)?);
+    }
+
+    let pow = options.pow.difficulty_get(&public_key).await?;
+
+    let mut wraps: Vec<(Event, Keys)> = Vec::new();
+
+    for template in templates {
+        let created_at = template.created_at
+            .or(options.created_at.map(Timestamp::from))
+            .unwrap_or_else(Timestamp::now);
+
+        let mut wrap_tags: Vec<Tag> = Vec::new();
+        if let Some(expiration) = options.expiration {
+            wrap_tags.push(Tag::expiration(
+                created_at + Duration::from_secs(expiration)
+            ));
+        }
+
+        let rumor: UnsignedEvent = EventBuilder::new(
+            template.kind, template.content
+        )
+            .tags(template.tags)
+            .tags(wrap_tags.clone())
+            .custom_created_at(created_at)
+            .build(public_key);
+
+        for receiver in &receivers_public_key {
+            let (wrap, ephemeral_keys) = gift_wrap_build(
+                signer, receiver, rumor.clone(), wrap_tags.clone(), pow
+            ).await?;
+
+            println!("{}", wrap.as_pretty_json());
+
+            wraps.push((wrap, ephemeral_keys));
+        }
+    }
+
+    if let Some(wrap_keys) = options.wrap_keys {
+        wrap_keys_write(signer, &wrap_keys, &wraps.iter()
+            .map(|(wrap, ephemeral_keys)| (wrap, ephemeral_keys))
+            .collect::<Vec<(&Event, &Keys)>>()
+        ).await?;
+    }
+
+    Ok(())
+}
+
+// seal and rumor of every gift wrap, with their verification
+async fn gift_unwrap(
+    signer: &Arc<dyn NostrSigner>, wraps: Vec<Event>
+) -> Result<(), Error> {
+    let mut wraps_failed: usize = 0;
+
+    for (index, wrap) in wraps.into_iter().enumerate() {
+        let mut report = GiftUnwrapReport {
+            event: index + 1,
+            id: wrap.id,
+            seal: None,
+            rumor: None,
+            error: None
+        };
+
+        match gift_wrap_open(signer, &wrap).await {
+            Ok(layers) => {
+                if ! layers.seal_errors.is_empty()
+                    || ! layers.rumor_errors.is_empty() {
+                    wraps_failed += 1;
+                }
+
+                report.seal = Some(GiftWrapLayerReport {
+                    valid: layers.seal_errors.is_empty(),
+                    errors: layers.seal_errors,
+                    event: serde_json::from_str(&layers.seal.as_json())?
+                });
+                report.rumor = Some(GiftWrapLayerReport {
+                    valid: layers.rumor_errors.is_empty(),
+                    errors: layers.rumor_errors,
+                    event: layers.rumor
+                });
+            },
+            Err(error) => {
+                wraps_failed += 1;
+                report.error = Some(format!("{error:#}"));
+            }
+        }
+
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+
+    if wraps_failed > 0 {
+        return Err(anyhow!("{wraps_failed} gift wraps failed verification"));
+    }
+
+    Ok(())
+}
+
+// https://github.com/nostr-protocol/nips/blob/master/17.md
+//
+// TODO: support tags:
+// ["e", "<kind-14-id>", "<relay-url>"] // if this is a reply
+// ["subject", "<conversation-title>"]
+// ["q", "<event-id> or <event-address>", "<relay-url>", "<pubkey-if-a-regular-event>"]
+//
+// TODO: support Kind::Custom(15)
+async fn dm_events(
+    signer: &Arc<dyn NostrSigner>, receiver_public_key: &str, message: &str,
+    options: DmEventsOptions
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+    let receiver = PublicKey::parse(receiver_public_key)?;
+
+    let created_at = options.created_at
+        .map(Timestamp::from)
+        .unwrap_or_else(Timestamp::now);
+
+    let mut wrap_tags: Vec<Tag> = Vec::new();
+    if let Some(expiration) = options.expiration {
+        wrap_tags.push(Tag::expiration(
+            created_at + Duration::from_secs(expiration)
+        ));
+    }
 
     let rumor: UnsignedEvent = EventBuilder::new(Kind::Custom(14), message)
         .tags([Tag::public_key(receiver)])
-        .build(keys.public_key());
+        .tags(wrap_tags.clone())
+        .custom_created_at(created_at)
+        .build(public_key);
+
+    let pow = options.pow.difficulty_get(&public_key).await?;
+
+    let (event_receiver, ephemeral_receiver) = gift_wrap_build(
+        signer, &receiver, rumor.clone(), wrap_tags.clone(), pow
+    ).await?;
+
+    let (event_self, ephemeral_self) = gift_wrap_build(
+        signer, &public_key, rumor, wrap_tags, pow
+    ).await?;
+
+    if let Some(wrap_keys) = options.wrap_keys {
+        wrap_keys_write(signer, &wrap_keys, &[
+            (&event_receiver, &ephemeral_receiver),
+            (&event_self, &ephemeral_self)
+        ]).await?;
+    }
+
+    println!("{}", event_receiver.as_pretty_json());
+    println!("{}", event_self.as_pretty_json());
+
+    Ok(())
+}
+
+// reaction (nip-25) to a message, gift wrapped to every participant of the
+// conversation and to ourself
+async fn dm_react(
+    signer: &Arc<dyn NostrSigner>, message: JsonOrdered, reaction: &str,
+    options: DmEventsOptions
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+
+    let message_id = EventId::parse(message.get("id")
+        .ok_or(anyhow!("id not present"))?
+        .as_str()
+        .ok_or(anyhow!("id not str"))?
+    )?;
+
+    // pubkey is an object with bech32 and hex in dm-fetch messages
+    let sender = message.get("pubkey")
+        .ok_or(anyhow!("pubkey not present"))?;
+    let sender = PublicKey::parse(sender.get("hex")
+        .unwrap_or(sender)
+        .as_str()
+        .ok_or(anyhow!("pubkey not str"))?
+    )?;
+
+    let mut participants: Vec<PublicKey> = vec![sender];
+    for tag in message.get("tags")
+        .ok_or(anyhow!("tags not present"))?
+        .as_array()
+        .ok_or(anyhow!("tags not array"))? {
+        let tag_array = tag
+            .as_array()
+            .ok_or(anyhow!("tag not array"))?;
+        if tag_array.len() >= 2 && tag_array[0].as_str()
+            .ok_or(anyhow!("tag element 0 not str"))?
+        == "p" {
+            let participant = PublicKey::parse(tag_array[1]
+                .as_str()
+                .ok_or(anyhow!("tag element 1 not str"))?
+            )?;
+            if ! participants.contains(&participant) {
+                participants.push(participant);
+            }
+        }
+    }
+    participants.retain(|participant| *participant != public_key);
+
+    let mut tags: Vec<Tag> = vec![Tag::event(message_id)];
+    for participant in &participants {
+        tags.push(Tag::public_key(*participant));
+    }
+    tags.push(Tag::from_standardized(TagStandard::Kind {
+        kind: Kind::PrivateDirectMessage, uppercase: false
+    }));
+
+    let mut receivers: Vec<String> = participants.iter()
+        .map(|participant| participant.to_hex())
+        .collect();
+    receivers.push(public_key.to_hex());
+
+    gift_wrap(signer, receivers, vec![EventTemplate {
+        kind: Kind::Reaction,
+        tags,
+        content: reaction.to_owned(),
+        created_at: None
+    }], options).await
+}
+
+// should be renamed gift_wraps_fetch then maybe an other function specific for
+// nip-17 private direct messages
+async fn dm_fetch(
+    signer: &Arc<dyn NostrSigner>, relays: Vec<String>
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+
+    let filter: Filter = Filter::new()
+        .kind(Kind::GiftWrap)
+        .pubkey(public_key);
+
+    let events = events_fetch_filter(
+        filter, relays, &isolation_get(&[public_key], false)
+    ).await?;
+
+    let mut messages: Vec<JsonOrdered> = Vec::new();
+    let mut reactions: Vec<(EventId, JsonOrdered)> = Vec::new();
+
+    for event in events.to_vec() {
+        if event.kind == Kind::GiftWrap {
+            let UnwrappedGift { sender, rumor } =
+                UnwrappedGift::from_gift_wrap(signer, &event).await?;
+
+            let mut extra_fields =
+                JsonOrdered::new();
+
+            if sender != rumor.pubkey {
+                extra_fields.insert(
+                    format!("warning"),
+                    serde_json::json!(format!(
+                        "pubkey of the sealed event is not the same as the \
+                         one in the rumor"
+                    ))
+                );
+                extra_fields.insert(
+                    format!("sealed"),
+                    serde_json::json!({
+                        "bech32": sender.to_bech32()?,
+                        "hex": sender.to_hex()
+                    })
+                );
+            }
+
+            let reaction_target = if rumor.kind == Kind::Reaction {
+                rumor.tags.event_ids().last().copied()
+            } else {
+                None
+            };
+
+            // TODO: handle Kind::Custom(15) better
+            let message = unsigned_event_json_get(rumor, Some(extra_fields))?;
+            // nip-40 expired messages are ignored
+            if message_is_expired(&message)? {
+                continue;
+            }
+            match reaction_target {
+                Some(id) => reactions.push((id, message)),
+                None => messages.push(message)
+            }
+        }
+    }
+
+    // reactions to messages in this batch are attached to them, the others
+    // are printed as they are, dm-save attaches them to the saved messages
+    for (id, reaction) in reactions {
+        let message = messages.iter_mut().find(|message| {
+            message.get("id").and_then(|id| id.as_str())
+                == Some(&id.to_hex())
+        });
+        match message {
+            Some(message) if message_reaction_allowed(message, &reaction)? => {
+                message_reactions_merge(message, vec![reaction])?;
+            },
+            _ => messages.push(reaction)
+        }
+    }
+
+    for message in messages {
+        println!("{}", serde_json::to_string_pretty(&message)?);
+    }
+
+    Ok(())
+}
+
+// nip-04 direct messages sent to and from the key, printed as the messages of
+// dm_fetch marked as legacy
+async fn dm_fetch_legacy(
+    signer: &Arc<dyn NostrSigner>, relays: Vec<String>
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+    let timeout = timeout_get();
+
+    let client = client_connected_relays_get(
+        &vec![relays], &isolation_get(&[public_key], false)
+    ).await?;
+
+    let mut events = client.fetch_events(
+        Filter::new()
+            .kind(Kind::EncryptedDirectMessage)
+            .pubkey(public_key),
+        timeout
+    ).await?;
+    events = events.merge(client.fetch_events(
+        Filter::new()
+            .kind(Kind::EncryptedDirectMessage)
+            .author(public_key),
+        timeout
+    ).await?);
+
+    client.disconnect().await;
+
+    for event in events.to_vec() {
+        let peer = if event.pubkey == public_key {
+            match event.tags.public_keys().next() {
+                Some(peer) => *peer,
+                None => {
+                    eprintln!("error: {} without p tag", event.id);
+                    continue;
+                }
+            }
+        } else {
+            event.pubkey
+        };
+
+        let content = match signer.nip04_decrypt(&peer, &event.content).await {
+            Ok(content) => content,
+            Err(error) => {
+                eprintln!("error: decrypting {} {error}", event.id);
+                continue;
+            }
+        };
+
+        let mut message: UnsignedEvent = event.into();
+        message.content = content;
+
+        let mut extra_fields = JsonOrdered::new();
+        extra_fields.insert(format!("legacy"), serde_json::json!(true));
+
+        unsigned_event_print(message, Some(extra_fields))?;
+    }
+
+    Ok(())
+}
+
+// add reactions to the reactions of a message, skipping the ones already
+// present
+fn message_reactions_merge(
+    message: &mut JsonOrdered, reactions: Vec<JsonOrdered>
+) -> Result<bool, Error> {
+    let message_reactions = message.entry(format!("reactions"))
+        .or_insert_with(|| serde_json::json!([]))
+        .as_array_mut()
+        .ok_or(anyhow!("reactions not array"))?;
+
+    let mut changed = false;
+    for reaction in reactions {
+        let reaction = serde_json::to_value(reaction)?;
+        if ! message_reactions.iter().any(|message_reaction| {
+            message_reaction.get("id") == reaction.get("id")
+        }) {
+            message_reactions.push(reaction);
+            changed = true;
+        }
+    }
+
+    Ok(changed)
+}
+
+// event id of the message a reaction points to, the last e tag
+fn message_reaction_target_get(message: &JsonOrdered) -> Result<Option<String>, Error> {
+    if message.get("kind").and_then(|kind| kind.as_u64())
+        != Some(Kind::Reaction.as_u16().into()) {
+        return Ok(None);
+    }
+
+    let mut target: Option<String> = None;
+    for tag in message.get("tags")
+        .ok_or(anyhow!("tags not present"))?
+        .as_array()
+        .ok_or(anyhow!("tags not array"))? {
+        let tag_array = tag
+            .as_array()
+            .ok_or(anyhow!("tag not array"))?;
+        if tag_array.len() >= 2 && tag_array[0].as_str()
+            .ok_or(anyhow!("tag element 0 not str"))?
+        == "e" {
+            target = Some(tag_array[1]
+                .as_str()
+                .ok_or(anyhow!("tag element 1 not str"))?
+                .to_owned()
+            );
+        }
+    }
+
+    Ok(target)
+}
+
+// hex public key of the author of a message, the one of the seal when it is
+// not the one in the rumor
+fn message_author_get(message: &JsonOrdered) -> Result<String, Error> {
+    let author = message.get("sealed")
+        .or(message.get("pubkey"))
+        .ok_or(anyhow!("pubkey not present"))?;
+
+    Ok(author.get("hex")
+        .and_then(|hex| hex.as_str())
+        .ok_or(anyhow!("pubkey hex not str"))?
+        .to_owned()
+    )
+}
+
+// a reaction is only attached to a message when its author is a participant
+// of the conversation, the author of the message or one of its p tags
+fn message_reaction_allowed(
+    message: &JsonOrdered, reaction: &JsonOrdered
+) -> Result<bool, Error> {
+    let author = message_author_get(reaction)?;
+    if author == message_author_get(message)? {
+        return Ok(true);
+    }
+
+    for tag in message.get("tags")
+        .ok_or(anyhow!("tags not present"))?
+        .as_array()
+        .ok_or(anyhow!("tags not array"))? {
+        let tag_array = tag
+            .as_array()
+            .ok_or(anyhow!("tag not array"))?;
+        if tag_array.len() >= 2
+            && tag_array[0].as_str() == Some("p")
+            && tag_array[1].as_str() == Some(&author) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+// saved message file with id, in any peer directory
+fn message_file_find(dir_save: &str, id: &str) -> Result<Option<String>, Error> {
+    for entry in std::fs::read_dir(dir_save)? {
+        let entry = entry?;
+        if ! entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        for message_file in dir_files_get(&entry.path().to_string_lossy())? {
+            if message_file.ends_with(&format!("-{id}")) {
+                return Ok(Some(message_file));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+// nip-40 expiration tag of a message, a malformed one is ignored as it comes
+// from the peer
+fn message_expiration_get(message: &JsonOrdered) -> Result<Option<u64>, Error> {
+    for tag in message.get("tags")
+        .ok_or(anyhow!("tags not present"))?
+        .as_array()
+        .ok_or(anyhow!("tags not array"))? {
+        let tag_array = tag
+            .as_array()
+            .ok_or(anyhow!("tag not array"))?;
+        if tag_array.len() >= 2 && tag_array[0].as_str()
+            .ok_or(anyhow!("tag element 0 not str"))?
+        == "expiration"
+            && let Some(expiration) = tag_array[1].as_str()
+                .and_then(|expiration| expiration.parse().ok()) {
+            return Ok(Some(expiration));
+        }
+    }
+
+    Ok(None)
+}
+
+fn message_is_expired(message: &JsonOrdered) -> Result<bool, Error> {
+    Ok(message_expiration_get(message)?
+        .is_some_and(|expiration| expiration <= Timestamp::now().as_u64())
+    )
+}
+
+fn dm_save(
+    messages: Vec<JsonOrdered>, public_key: &str, dir_save: &str
+) -> Result<(), Error> {
+    let self_public_key = PublicKey::parse(public_key)?;
+
+    if ! path_exists(dir_save) {
+        mkdir(dir_save)
+            .with_context(|| "creating save directory")?;
+    }
+
+    for message in messages {
+        if message_is_expired(&message)? {
+            continue;
+        }
+
+        if let Some(target) = message_reaction_target_get(&message)? {
+            match message_file_find(dir_save, &target)? {
+                Some(message_file) => {
+                    message_file_reactions_merge(&message_file, vec![message])?;
+                },
+                None => eprintln!("reaction to message {target} not saved")
+            }
+            continue;
+        }
+
+        let sender_bech32 = message.get("pubkey")
+            .ok_or(anyhow!("sender not present"))?
+            .as_object()
+            .ok_or(anyhow!("sender not obj"))?
+            .get("bech32")
+            .ok_or(anyhow!("bech32 not present"))?
+            .as_str()
+            .ok_or(anyhow!("bech32 not str"))?;
+        let peer_bech32 = if sender_bech32 != self_public_key.to_bech32()? {
+            sender_bech32
+        } else {
+            // TODO: handle multiple p tags
+
+            let mut tag_public_key: Option<&str> = None;
+
+            for tag in message.get("tags")
+                .ok_or(anyhow!("tags not present"))?
+                .as_array()
+                .ok_or(anyhow!("tags not array"))? {
+                let tag_array = tag
+                    .as_array()
+                    .ok_or(anyhow!("tag not array"))?;
+                if tag_array[0].as_str()
+                    .ok_or(anyhow!("tag element 0 not str"))?
+                == "p" && tag_array.len() >= 2 {
+                    tag_public_key = Some(tag_array[1]
+                        .as_str()
+                        .ok_or(anyhow!("tag element 1 not str"))?
+                    );
+                    break;
+                }
+            }
+
+            match tag_public_key {
+                Some(public_key) => {
+                    &PublicKey::parse(public_key)?.to_bech32()?
+                },
+                None => return Err(anyhow!("p tag not found"))
+            }
+        };
+
+        let peer_dir = format!("{dir_save}/{peer_bech32}");
+        if ! path_exists(&peer_dir) {
+            mkdir(&peer_dir)
+                .with_context(|| "creating peer directory")?;
+        }
+
+        let message_id = message.get("id")
+            .ok_or(anyhow!("id not present"))?
+            .as_str()
+            .ok_or(anyhow!("id not str"))?;
+
+        let created_at = message.get("created_at")
+            .ok_or(anyhow!("created_at not present"))?
+            .as_object()
+            .ok_or(anyhow!("created_at not object"))?
+            .get("timestamp")
+            .ok_or(anyhow!("timestamp not present"))?
+            .as_number()
+            .ok_or(anyhow!("timestamp not number"))?
+            .as_u64()
+            .ok_or(anyhow!("timestamp not u64"))?;
+
+        let message_file = format!("{peer_dir}/{created_at}-{message_id}");
+        if ! path_exists(&message_file) {
+            file_write(
+                &message_file, &(serde_json::to_string_pretty(&message)? + "\n")
+            )?;
+        } else if let Some(reactions) = message.get("reactions") {
+            message_file_reactions_merge(
+                &message_file,
+                serde_json::from_value(reactions.clone())
+                    .with_context(|| "parsing reactions")?
+            )?;
+        }
+    }
 
-    let event_receiver: Event = EventBuilder::gift_wrap(
-        &keys, &receiver, rumor.clone(), []
-    ).await?;
+    Ok(())
+}
 
-    let event_self: Event = EventBuilder::gift_wrap(
-        &keys, &keys.public_key(), rumor, []
-    ).await?;
+fn message_file_reactions_merge(
+    message_file: &str, reactions: Vec<JsonOrdered>
+) -> Result<(), Error> {
+    let mut message: JsonOrdered = serde_json::from_str(
+        &file_read(message_file)?
+    ).with_context(|| format!("parsing message {message_file}"))?;
+
+    let mut reactions_allowed: Vec<JsonOrdered> = Vec::new();
+    for reaction in reactions {
+        if message_reaction_allowed(&message, &reaction)? {
+            reactions_allowed.push(reaction);
+        } else {
+            eprintln!(
+                "reaction to {message_file} not from a participant, ignored"
+            );
+        }
+    }
 
-    println!("{}", event_receiver.as_pretty_json());
-    println!("{}", event_self.as_pretty_json());
+    if message_reactions_merge(&mut message, reactions_allowed)? {
+        file_write(
+            message_file, &(serde_json::to_string_pretty(&message)? + "\n")
+        )?;
+    }
 
     Ok(())
 }
 
-// should be renamed gift_wraps_fetch then maybe an other function specific for
-// nip-17 private direct messages
-async fn dm_fetch(
-    private_key: &str, relays: Vec<String>
+// remove the saved messages that are expired, printing their paths
+fn dm_purge_expired(dir_save: &str) -> Result<(), Error> {
+    if ! path_exists(dir_save) {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir_save)? {
+        let entry = entry?;
+        if ! entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        for message_file in dir_files_get(&entry.path().to_string_lossy())? {
+            let message: JsonOrdered = serde_json::from_str(
+                &file_read(&message_file)?
+            ).with_context(|| format!("parsing message {message_file}"))?;
+
+            if message_is_expired(&message)? {
+                file_remove(&message_file)
+                    .with_context(|| "removing expired message")?;
+                println!("{message_file}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+// deletion requests for the gift wraps in a wrap keys file, each one signed
+// with the ephemeral key of its gift wrap and sent to the relays of its gift
+// wrap
+async fn dm_retract(
+    signer: &Arc<dyn NostrSigner>, wrap_keys: &str, options: EventsSendOptions
 ) -> Result<(), Error> {
-    let keys = Keys::parse(private_key)?;
+    let public_key = signer.get_public_key().await?;
 
-    let filter: Filter = Filter::new()
-        .kind(Kind::GiftWrap)
-        .pubkey(keys.public_key());
+    let mut events: Vec<Event> = Vec::new();
+    let mut relays_list: Vec<Vec<String>> = Vec::new();
 
-    let events = events_fetch_filter(filter, relays).await?;
+    for entry in serde_json::Deserializer::from_str(
+        &file_read(wrap_keys).with_context(|| "reading wrap keys")?
+    ).into_iter::<WrapKeyEntry>() {
+        let entry = entry.with_context(|| "deserializing wrap key")?;
 
-    for event in events.to_vec() {
-        if event.kind == Kind::GiftWrap {
-            let UnwrappedGift { sender, rumor } =
-                UnwrappedGift::from_gift_wrap(&keys, &event).await?;
+        let key = signer.nip44_decrypt(&public_key, &entry.key).await
+            .with_context(|| format!("decrypting wrap key of {}", entry.id))?;
+        let ephemeral_keys = Keys::parse(&key)?;
 
-            let mut extra_fields =
-                JsonOrdered::new();
+        if ephemeral_keys.public_key() != entry.pubkey {
+            return Err(anyhow!("wrap key of {} does not match", entry.id));
+        }
 
-            if sender != rumor.pubkey {
-                extra_fields.insert(
-                    format!("warning"),
-                    serde_json::json!(format!(
-                        "pubkey of the sealed event is not the same as the \
-                         one in the rumor"
-                    ))
-                );
-                extra_fields.insert(
-                    format!("sealed"),
-                    serde_json::json!({
-                        "bech32": sender.to_bech32()?,
-                        "hex": sender.to_hex()
-                    })
-                );
+        if entry.relays.is_empty() {
+            return Err(anyhow!(
+                "relays of {} not recorded, send the gift wraps with the \
+                 wrap_keys send option",
+                entry.id
+            ));
+        }
+
+        let event: Event = EventBuilder::delete(
+            EventDeletionRequest::new().id(entry.id)
+        )
+            .tag(Tag::from_standardized(TagStandard::Kind {
+                kind: Kind::GiftWrap, uppercase: false
+            }))
+            .sign_with_keys(&ephemeral_keys)?;
+
+        events.push(event);
+        relays_list.push(entry.relays);
+    }
+
+    events_send(events, relays_list, options, |_| Ok(())).await
+}
+
+// relays of the newest inbox relay list (nip-17) of public key
+async fn inbox_relays_fetch(
+    public_key: &PublicKey, relays: Vec<String>, isolation: &str
+) -> Result<Vec<String>, Error> {
+    let filter = Filter::new()
+        .author(*public_key)
+        .kind(Kind::InboxRelays);
+
+    let mut inbox_relays: Vec<String> = Vec::new();
+
+    let events = events_fetch_filter(filter, relays, isolation).await?;
+    if let Some(event) = events.first_owned() {
+        for tag in event.tags.iter() {
+            if let [tag_kind, relay, ..] = tag.as_slice()
+                && tag_kind == "relay" && ! inbox_relays.contains(relay) {
+                inbox_relays.push(relay.clone());
             }
+        }
+    }
 
-            // TODO: handle Kind::Custom(15) better
-            unsigned_event_print(rumor, Some(extra_fields))?;
+    Ok(inbox_relays)
+}
+
+// request to vanish (nip-62) from relays, also from our inbox relays so the
+// gift wraps addressed to us are deleted, or from every relay
+// on stderr, for every relay, if it lists nip-62 in its information document
+async fn vanish_event(
+    signer: &Arc<dyn NostrSigner>, relays: Vec<String>, options: VanishOptions
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+    let public_key_bech32 = public_key.to_bech32()?;
+
+    let isolation = isolation_get(&[public_key], false);
+
+    let mut relays_vanish = relays_normalize(&relays)?;
+    for relay in relays_normalize(&inbox_relays_fetch(
+        &public_key, relays.clone(), &isolation
+    ).await?)? {
+        if ! relays_vanish.contains(&relay) {
+            relays_vanish.push(relay);
+        }
+    }
+
+    let target = if options.all_relays {
+        VanishTarget::AllRelays
+    } else {
+        let mut relays_url: Vec<RelayUrl> = Vec::new();
+        for relay in &relays_vanish {
+            relays_url.push(RelayUrl::parse(relay)?);
+        }
+        VanishTarget::Relays(relays_url)
+    };
+
+    for relay in &relays_vanish {
+        match relay_information_get(relay, &isolation).await {
+            Ok(information) => {
+                if information.supported_nips
+                    .is_some_and(|nips| nips.contains(&62)) {
+                    eprintln!("{relay}: supports nip-62");
+                } else {
+                    eprintln!("{relay}: does not list nip-62");
+                }
+            },
+            Err(error) => eprintln!("{relay}: information error {error:#}")
         }
     }
 
+    let target_description = if options.all_relays {
+        format!("every relay")
+    } else {
+        relays_vanish.join(" ")
+    };
+    let confirm = match options.confirm {
+        Some(confirm) => confirm,
+        None => tty_line_read(&format!(
+            "request to vanish {public_key_bech32} from {target_description}\n\
+             every event of the key will be deleted, it can not be undone\n\
+             type the npub to confirm: "
+        ))?
+    };
+    if PublicKey::parse(&confirm).ok() != Some(public_key) {
+        return Err(anyhow!("request to vanish not confirmed"));
+    }
+
+    let event: Event = EventBuilder::request_vanish_with_reason(
+        target, options.reason
+    )?.sign(signer).await?;
+
+    eprintln!("send to {}", serde_json::to_string(&relays_vanish)?);
+
+    println!("{}", event.as_pretty_json());
+
     Ok(())
 }
 
-fn dm_save(
-    messages: Vec<JsonOrdered>, public_key: &str, dir_save: &str
+// deletion request (nip-09) for event ids and coordinates, the events of the
+// ids are fetched from relays to check that they are ours
+async fn delete_event(
+    signer: &Arc<dyn NostrSigner>, targets: Vec<String>, relays: Vec<String>,
+    options: DeleteOptions
 ) -> Result<(), Error> {
-    let self_public_key = PublicKey::parse(public_key)?;
+    let public_key = signer.get_public_key().await?;
 
-    if ! path_exists(dir_save) {
-        mkdir(dir_save)
-            .with_context(|| "creating save directory")?;
+    let mut ids: Vec<EventId> = Vec::new();
+    let mut coordinates: Vec<Coordinate> = Vec::new();
+    let mut kinds: Vec<Kind> = Vec::new();
+
+    for target in &targets {
+        if let Ok(id) = EventId::parse(target) {
+            ids.push(id);
+            continue;
+        }
+
+        let coordinate = Coordinate::parse(target)
+            .with_context(|| format!("parsing {target} as id or coordinate"))?;
+        if coordinate.public_key != public_key {
+            return Err(anyhow!("coordinate {target} is not of the key"));
+        }
+        if ! kinds.contains(&coordinate.kind) {
+            kinds.push(coordinate.kind);
+        }
+        coordinates.push(coordinate);
     }
 
-    for message in messages {
-        let sender_bech32 = message.get("pubkey")
-            .ok_or(anyhow!("sender not present"))?
-            .as_object()
-            .ok_or(anyhow!("sender not obj"))?
-            .get("bech32")
-            .ok_or(anyhow!("bech32 not present"))?
-            .as_str()
-            .ok_or(anyhow!("bech32 not str"))?;
-        let peer_bech32 = if sender_bech32 != self_public_key.to_bech32()? {
-            sender_bech32
+    if ! ids.is_empty() {
+        let events = events_fetch_filter(
+            Filter::new().ids(ids.clone()),
+            relays,
+            &isolation_get(&[public_key], false)
+        ).await?;
+
+        for id in &ids {
+            let event = events.iter().find(|event| event.id == *id)
+                .ok_or(anyhow!("event {id} not found on relays"))?;
+            if event.pubkey != public_key {
+                return Err(anyhow!("event {id} was not authored by the key"));
+            }
+            if ! kinds.contains(&event.kind) {
+                kinds.push(event.kind);
+            }
+        }
+    }
+
+    if ids.is_empty() && coordinates.is_empty() {
+        return Err(anyhow!("insert at least one id or coordinate"));
+    }
+
+    let mut request = EventDeletionRequest::new()
+        .ids(ids)
+        .coordinates(coordinates);
+    if let Some(reason) = options.reason {
+        request = request.reason(reason);
+    }
+
+    let event: Event = EventBuilder::delete(request)
+        .tags(kinds.into_iter().map(|kind| {
+            Tag::from_standardized(TagStandard::Kind { kind, uppercase: false })
+        }))
+        .sign(signer).await?;
+
+    println!("{}", event.as_pretty_json());
+
+    Ok(())
+}
+
+struct DoctorChecklist {
+    failed: usize
+}
+
+impl DoctorChecklist {
+    fn new() -> Self {
+        Self { failed: 0 }
+    }
+
+    fn pass(&mut self, check: &str) {
+        println!("[pass] {check}");
+    }
+
+    fn fail(&mut self, check: &str, fix: &str) {
+        self.failed += 1;
+        println!("[fail] {check}");
+        println!("       fix: {fix}");
+    }
+}
+
+fn doctor_kind_name_get(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::Metadata => "metadata (kind:0)",
+        Kind::RelayList => "relay list (kind:10002)",
+        Kind::InboxRelays => "inbox relays (kind:10050)",
+        _ => "event"
+    }
+}
+
+fn doctor_kind_fix_get(kind: &Kind) -> &'static str {
+    match kind {
+        Kind::Metadata => "nmini metadata-event <metadata-json>",
+        Kind::RelayList => "nmini relay-list-event standard <relays>",
+        Kind::InboxRelays => "nmini relay-list-event inbox <inbox-relays>",
+        _ => "nmini events-send <relays>"
+    }
+}
+
+// check the nip-17 setup of the key, fetching from the bootstrap relays:
+// proxy, kind:0, kind:10002 and kind:10050 present and equal on every
+// relay, inbox relays reachable and storing and returning gift wraps
+async fn doctor(
+    signer: &Arc<dyn NostrSigner>, relays: Vec<String>
+) -> Result<(), Error> {
+    let public_key = signer.get_public_key().await?;
+    let public_key_bech32 = public_key.to_bech32()?;
+    let timeout = timeout_get();
+
+    let isolation = isolation_get(&[public_key], false);
+
+    let mut checklist = DoctorChecklist::new();
+
+    match proxy_transport_get(&isolation)?.default {
+        ProxyMode::Direct => checklist.pass("clearnet mode, no proxy"),
+        ProxyMode::Socks { proxy, .. } => {
+            let check = format!("proxy on {proxy}");
+            match socks_proxy_check(&proxy).await {
+                Ok(_) => checklist.pass(&check),
+                Err(error) => {
+                    checklist.fail(
+                        &format!("{check}: {error:#}"),
+                        &format!(
+                            "start tor and check SocksPort is {proxy} \
+                             or set NMINI_PROXY"
+                        )
+                    );
+                    return Err(anyhow!(
+                        "proxy not working, other checks skipped"
+                    ));
+                }
+            }
+        }
+    }
+
+    let client = client_connected_relays_get(
+        &vec![relays.clone()], &isolation
+    ).await?;
+
+    let mut relays_connected: Vec<String> = Vec::new();
+    for relay in &relays {
+        let check = format!("bootstrap relay {relay} reachable");
+        if client.relay(relay).await?.is_connected() {
+            relays_connected.push(relay.clone());
+            checklist.pass(&check);
         } else {
-            // TODO: handle multiple p tags
+            checklist.fail(&check, "check the url or remove the relay");
+        }
+    }
 
-            let mut tag_public_key: Option<&str> = None;
+    let mut inbox_relays: Vec<String> = Vec::new();
+
+    for kind in [Kind::Metadata, Kind::RelayList, Kind::InboxRelays] {
+        let kind_name = doctor_kind_name_get(&kind);
+        let filter = Filter::new()
+            .author(public_key)
+            .kind(kind);
+
+        let mut newest: Option<Event> = None;
+        let mut relays_missing: Vec<String> = Vec::new();
+        let mut relays_ids: Vec<EventId> = Vec::new();
+
+        for relay in &relays_connected {
+            let event = match client.fetch_events_from(
+                [relay], filter.clone(), timeout
+            ).await {
+                Ok(events) => events.first_owned(),
+                Err(error) => {
+                    checklist.fail(
+                        &format!("{kind_name} fetch from {relay}: {error}"),
+                        "check the relay or remove it"
+                    );
+                    continue;
+                }
+            };
+
+            match event {
+                Some(event) => {
+                    if ! relays_ids.contains(&event.id) {
+                        relays_ids.push(event.id);
+                    }
+                    if newest.as_ref().is_none_or(
+                        |newest| event.created_at > newest.created_at
+                    ) {
+                        newest = Some(event);
+                    }
+                },
+                None => relays_missing.push(relay.clone())
+            }
+        }
 
-            for tag in message.get("tags")
-                .ok_or(anyhow!("tags not present"))?
-                .as_array()
-                .ok_or(anyhow!("tags not array"))? {
-                let tag_array = tag
-                    .as_array()
-                    .ok_or(anyhow!("tag not array"))?;
-                if tag_array[0].as_str()
-                    .ok_or(anyhow!("tag element 0 not str"))?
-                == "p" && tag_array.len() >= 2 {
-                    tag_public_key = Some(tag_array[1]
-                        .as_str()
-                        .ok_or(anyhow!("tag element 1 not str"))?
+        let republish = format!(
+            "nmini events-fetch {public_key_bech32} '[{}]' <relays> '{{}}' \
+             | nmini events-send <relays>",
+            kind.as_u16()
+        );
+
+        let check = format!("{kind_name} present");
+        let newest = match newest {
+            Some(newest) => newest,
+            None => {
+                checklist.fail(
+                    &check,
+                    &format!(
+                        "{} | nmini events-send <relays>",
+                        doctor_kind_fix_get(&kind)
+                    )
+                );
+                continue;
+            }
+        };
+
+        if relays_missing.is_empty() {
+            checklist.pass(&check);
+        } else {
+            checklist.fail(
+                &format!("{check}, missing on {}", relays_missing.join(" ")),
+                &republish
+            );
+        }
+
+        let check = format!("{kind_name} same on every relay");
+        if relays_ids.len() <= 1 {
+            checklist.pass(&check);
+        } else {
+            checklist.fail(
+                &format!("{check}, found {} different events", relays_ids.len()),
+                &republish
+            );
+        }
+
+        if kind == Kind::InboxRelays {
+            for tag in newest.tags.iter() {
+                if let [tag_kind, relay, ..] = tag.as_slice()
+                    && tag_kind == "relay" && ! inbox_relays.contains(relay) {
+                    inbox_relays.push(relay.clone());
+                }
+            }
+
+            let check = format!("{kind_name} has relays");
+            if inbox_relays.is_empty() {
+                checklist.fail(&check, doctor_kind_fix_get(&kind));
+            } else {
+                checklist.pass(&check);
+            }
+        }
+    }
+
+    client.disconnect().await;
+
+    if ! inbox_relays.is_empty() {
+        // self addressed test gift wrap, with an expiration so relays
+        // supporting nip-40 can delete it, the rumor is already expired so
+        // dm-fetch and dm-save skip it
+        let rumor: UnsignedEvent = EventBuilder::new(
+            Kind::Custom(14), "nmini doctor test"
+        )
+            .tags([
+                Tag::public_key(public_key),
+                Tag::expiration(Timestamp::now())
+            ])
+            .build(public_key);
+        let test_wrap: Event = EventBuilder::gift_wrap(
+            signer, &public_key, rumor,
+            [Tag::expiration(Timestamp::now() + Duration::from_secs(10 * 60))]
+        ).await?;
+
+        let client = client_connected_relays_get(
+            &vec![inbox_relays.clone()], &isolation
+        ).await?;
+
+        for relay in &inbox_relays {
+            let check = format!("inbox relay {relay} reachable");
+            if client.relay(relay).await?.is_connected() {
+                checklist.pass(&check);
+            } else {
+                checklist.fail(
+                    &check, "check the url or replace the inbox relay"
+                );
+                continue;
+            }
+
+            let check = format!("inbox relay {relay} accepts gift wraps");
+            let output = client.send_event_to([relay], &test_wrap).await?;
+            if let Some(message) = output.failed.values().next() {
+                checklist.fail(
+                    &format!("{check}: {message}"),
+                    "replace the inbox relay with one accepting kind:1059"
+                );
+                continue;
+            }
+            checklist.pass(&check);
+
+            let check = format!("inbox relay {relay} returns gift wraps");
+            let returned = match client.fetch_events_from(
+                [relay],
+                Filter::new()
+                    .id(test_wrap.id)
+                    .kind(Kind::GiftWrap)
+                    .pubkey(public_key),
+                timeout
+            ).await {
+                Ok(events) => events.contains(&test_wrap),
+                Err(error) => {
+                    checklist.fail(
+                        &format!("{check}: {error}"),
+                        "check the inbox relay or replace it"
                     );
-                    break;
+                    continue;
                 }
+            };
+            if returned {
+                checklist.pass(&check);
+            } else {
+                checklist.fail(
+                    &check,
+                    "replace the inbox relay, nmini can not read gift \
+                     wraps from it (it may require nip-42 auth)"
+                );
+            }
+        }
+
+        client.disconnect().await;
+    }
+
+    if checklist.failed > 0 {
+        return Err(anyhow!("{} checks failed", checklist.failed));
+    }
+
+    Ok(())
+}
+
+// bunker clients with their permissions, every request is logged on stderr
+struct BunkerClients {
+    clients: HashMap<PublicKey, BunkerPermissions>,
+    // required to connect when set
+    secret: Option<String>
+}
+
+impl NostrConnectSignerActions for BunkerClients {
+    fn approve(
+        &self, public_key: &PublicKey, req: &NostrConnectRequest
+    ) -> bool {
+        // unknown clients have no permissions
+        let none = BunkerPermissions::default();
+        let permissions = self.clients.get(public_key).unwrap_or(&none);
+
+        let (allowed, details) = match req {
+            NostrConnectRequest::Connect { secret, .. } => (
+                self.clients.contains_key(public_key)
+                    && (self.secret.is_none() || *secret == self.secret),
+                String::new()
+            ),
+            NostrConnectRequest::GetPublicKey
+            | NostrConnectRequest::Ping => {
+                (self.clients.contains_key(public_key), String::new())
+            },
+            NostrConnectRequest::SignEvent(unsigned) => (
+                permissions.kinds.contains(&unsigned.kind),
+                format!(" kind {}", unsigned.kind.as_u16())
+            ),
+            NostrConnectRequest::Nip04Encrypt { public_key, .. } => {
+                (permissions.nip04_encrypt, format!(" to {public_key}"))
+            },
+            NostrConnectRequest::Nip04Decrypt { public_key, .. } => {
+                (permissions.nip04_decrypt, format!(" from {public_key}"))
+            },
+            NostrConnectRequest::Nip44Encrypt { public_key, .. } => {
+                (permissions.nip44_encrypt, format!(" to {public_key}"))
+            },
+            NostrConnectRequest::Nip44Decrypt { public_key, .. } => {
+                (permissions.nip44_decrypt, format!(" from {public_key}"))
             }
+        };
+
+        eprintln!(
+            "{} {public_key} {}{details} {}",
+            Local::now().format(datetime_human_readable_format_get()),
+            req.method(),
+            if allowed { "allowed" } else { "denied" }
+        );
+
+        allowed
+    }
+}
+
+// nip-46 remote signer for the clients, on the relays, printing its
+// bunker:// uri, the key is also the one of the signer
+async fn bunker(
+    private_key: &str, relays: Vec<String>,
+    clients: HashMap<String, BunkerPermissions>, secret: Option<String>
+) -> Result<(), Error> {
+    let keys = Keys::parse(private_key)?;
+
+    let mut clients_permissions: HashMap<PublicKey, BunkerPermissions> =
+        HashMap::new();
+    for (client, permissions) in clients {
+        clients_permissions.insert(
+            PublicKey::parse(&client)
+                .with_context(|| format!("parsing client {client}"))?,
+            permissions
+        );
+    }
+
+    let mut relays_url: Vec<RelayUrl> = Vec::new();
+    for relay in &relays {
+        relays_url.push(RelayUrl::parse(relay)?);
+    }
+
+    let mode = signer_connection_mode_get(&relays_url)?;
+
+    let signer = NostrConnectRemoteSigner::new(
+        NostrConnectKeys { signer: keys.clone(), user: keys },
+        relays_url,
+        secret.clone(),
+        Some(RelayOptions::new().connection_mode(mode))
+    )?;
+
+    println!("{}", signer.bunker_uri());
+
+    signer.serve(BunkerClients {
+        clients: clients_permissions,
+        secret
+    }).await?;
+
+    Ok(())
+}
+
+// ask in the terminal of the agent to allow a request
+async fn agent_confirm(
+    state: &AgentState, description: &str
+) -> Result<bool, Error> {
+    let _confirm_lock = state.confirm_lock.lock().await;
+
+    let prompt = format!("allow {description}? [y/N] ");
+    let answer = tokio::task::spawn_blocking(move || {
+        tty_foreground_check()?;
+        tty_line_read(&prompt)
+    }).await??;
+
+    Ok(answer == "y")
+}
+
+async fn agent_request_answer(
+    state: &AgentState, request: &str, pid: Option<i32>
+) -> Result<serde_json::Value, Error> {
+    let request: AgentRequest = serde_json::from_str(request)
+        .with_context(|| "parsing request")?;
+
+    let details = match (&request.event, &request.peer) {
+        (Some(event), _) => format!(" kind {}", event.kind.as_u16()),
+        (None, Some(peer)) => format!(" {peer}"),
+        (None, None) => String::new()
+    };
+    let description = format!(
+        "pid {} {} {}{details}",
+        pid.map(|pid| pid.to_string()).unwrap_or(format!("unknown")),
+        request.public_key,
+        request.method
+    );
+
+    let supported = [
+        "get_public_key", "sign_event", "nip04_encrypt", "nip04_decrypt",
+        "nip44_encrypt", "nip44_decrypt"
+    ].contains(&request.method.as_str());
+
+    let keys = state.keys.iter()
+        .find(|keys| keys.public_key() == request.public_key);
+    let allowed = match keys {
+        Some(_) if ! supported => false,
+        Some(_) if state.confirm && request.method != "get_public_key" => {
+            agent_confirm(state, &description).await?
+        },
+        Some(_) => true,
+        None => false
+    };
+
+    eprintln!(
+        "{} {description} {}",
+        Local::now().format(datetime_human_readable_format_get()),
+        if allowed { "allowed" } else { "denied" }
+    );
+
+    let keys = keys.ok_or(anyhow!("key not in the agent"))?;
+    if ! supported {
+        return Err(anyhow!("method {} not supported", request.method));
+    }
+    if ! allowed {
+        return Err(anyhow!("request denied"));
+    }
+
+    let peer = request.peer.ok_or(anyhow!("peer not present"));
+    let content = request.content.as_deref()
+        .ok_or(anyhow!("content not present"));
+
+    Ok(match request.method.as_str() {
+        "get_public_key" => serde_json::json!(keys.public_key()),
+        "sign_event" => {
+            let event = request.event.ok_or(anyhow!("event not present"))?;
+            serde_json::to_value(event.sign_with_keys(keys)?)?
+        },
+        "nip04_encrypt" => serde_json::json!(
+            nip04::encrypt(keys.secret_key(), &peer?, content?)?
+        ),
+        "nip04_decrypt" => serde_json::json!(
+            nip04::decrypt(keys.secret_key(), &peer?, content?)?
+        ),
+        "nip44_encrypt" => serde_json::json!(nip44::encrypt(
+            keys.secret_key(), &peer?, content?, nip44::Version::default()
+        )?),
+        "nip44_decrypt" => serde_json::json!(
+            nip44::decrypt(keys.secret_key(), &peer?, content?)?
+        ),
+        method => return Err(anyhow!("method {method} not supported"))
+    })
+}
 
-            match tag_public_key {
-                Some(public_key) => {
-                    &PublicKey::parse(public_key)?.to_bech32()?
-                },
-                None => return Err(anyhow!("p tag not found"))
+// answer the requests of a connection, only from our own user
+async fn agent_connection_handle(
+    state: Arc<AgentState>, stream: tokio::net::UnixStream
+) -> Result<(), Error> {
+    use tokio::io::AsyncBufReadExt;
+
+    let credentials = stream.peer_cred()?;
+    if credentials.uid() != unsafe { libc::getuid() } {
+        return Err(anyhow!("connection of user {}", credentials.uid()));
+    }
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = tokio::io::BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        let response = match agent_request_answer(
+            &state, &line, credentials.pid()
+        ).await {
+            Ok(result) => AgentResponse { result: Some(result), error: None },
+            Err(error) => AgentResponse {
+                result: None,
+                error: Some(format!("{error:#}"))
             }
         };
 
-        let peer_dir = format!("{dir_save}/{peer_bech32}");
-        if ! path_exists(&peer_dir) {
-            mkdir(&peer_dir)
-                .with_context(|| "creating peer directory")?;
-        }
+        let mut response = serde_json::to_string(&response)?;
+        response += "\n";
+        writer.write_all(response.as_bytes()).await?;
+    }
 
-        let message_id = message.get("id")
-            .ok_or(anyhow!("id not present"))?
-            .as_str()
-            .ok_or(anyhow!("id not str"))?;
+    Ok(())
+}
 
-        let created_at = message.get("created_at")
-            .ok_or(anyhow!("created_at not present"))?
-            .as_object()
-            .ok_or(anyhow!("created_at not object"))?
-            .get("timestamp")
-            .ok_or(anyhow!("timestamp not present"))?
-            .as_number()
-            .ok_or(anyhow!("timestamp not number"))?
-            .as_u64()
-            .ok_or(anyhow!("timestamp not u64"))?;
+// hold the keys in locked memory, signing and encrypting with them for the
+// clients on the socket, until lifetime or idle_timeout or a signal, logging
+// every request on stderr
+async fn agent(
+    keys: Vec<Keys>, socket: &str, options: AgentOptions
+) -> Result<(), Error> {
+    use tokio::signal::unix::{signal, SignalKind};
 
-        let message_file = format!("{peer_dir}/{created_at}-{message_id}");
-        if ! path_exists(&message_file) {
-            file_write(
-                &message_file, &(serde_json::to_string_pretty(&message)? + "\n")
-            )?;
-        }
+    let state = Arc::new(AgentState {
+        keys,
+        confirm: options.confirm,
+        confirm_lock: tokio::sync::Mutex::new(())
+    });
+
+    memory_lock(&state.keys).with_context(|| "locking keys memory")?;
+
+    if state.confirm {
+        tty_foreground_check()
+            .with_context(|| "confirm needs the agent in the foreground")?;
     }
 
-    Ok(())
+    if path_exists(socket) {
+        return Err(anyhow!("{socket} already exists"));
+    }
+
+    // socket only accessible by the user
+    let umask = unsafe { libc::umask(0o177) };
+    let listener = tokio::net::UnixListener::bind(socket);
+    unsafe { libc::umask(umask) };
+    let listener = listener
+        .with_context(|| format!("listening on {socket}"))?;
+
+    eprintln!("agent with {} keys on {socket}", state.keys.len());
+
+    let lifetime_end = options.lifetime.map(|lifetime| {
+        tokio::time::Instant::now() + Duration::from_secs(lifetime)
+    });
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    let result = loop {
+        let end = [
+            lifetime_end,
+            options.idle_timeout.map(|idle_timeout| {
+                tokio::time::Instant::now() + Duration::from_secs(idle_timeout)
+            })
+        ].into_iter().flatten().min();
+        let expired = async {
+            match end {
+                Some(end) => tokio::time::sleep_until(end).await,
+                None => std::future::pending().await
+            }
+        };
+
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, _) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(error) => break Err(error.into())
+                };
+                let state = state.clone();
+                tokio::spawn(async move {
+                    if let Err(error) =
+                        agent_connection_handle(state, stream).await {
+                        eprintln!("error: {error:#}");
+                    }
+                });
+            },
+            _ = expired => {
+                eprintln!("agent expired");
+                break Ok(());
+            },
+            _ = terminate.recv() => break Ok(()),
+            _ = interrupt.recv() => break Ok(())
+        }
+    };
+
+    file_remove(socket)?;
+
+    result
 }
 
 fn stdin_events_array<T>() -> Result<Vec<T>, Error>
@@ -522,11 +4006,194 @@ where
     Ok(events)
 }
 
-fn stdin_key() -> Result<String, Error> {
-    Ok(stdin_pipe_read()
+// a nip-49 encrypted secret key is decrypted asking for its password
+fn key_decrypt(key: &str) -> Result<Zeroizing<String>, Error> {
+    if ! key.starts_with("ncryptsec1") {
+        return Ok(Zeroizing::new(key.to_owned()));
+    }
+
+    let encrypted_secret_key = nip49::EncryptedSecretKey::from_bech32(key)
+        .with_context(|| "parsing ncryptsec")?;
+
+    Ok(Zeroizing::new(
+        encrypted_secret_key.decrypt(&password_read("password: ", false)?)
+            .with_context(|| "decrypting ncryptsec")?
+            .to_bech32()?
+    ))
+}
+
+// auth_url of the remote signer printed on stderr, to be opened to approve
+// the request
+#[derive(Debug)]
+struct SignerAuthUrlHandler;
+
+impl AuthUrlHandler for SignerAuthUrlHandler {
+    fn on_auth_url(
+        &self, auth_url: Url
+    ) -> BoxedFuture<'_, nostr_connect::prelude::Result<()>> {
+        Box::pin(async move {
+            eprintln!("remote signer asks to open {auth_url}");
+            Ok(())
+        })
+    }
+}
+
+// proxy of the nostr connect relays, the same for all of them and without
+// socks credentials, as nostr connect uses its own relay pool
+fn signer_connection_mode_get(
+    relays: &[RelayUrl]
+) -> Result<ConnectionMode, Error> {
+    let proxy_transport = proxy_transport_get("")?;
+
+    let mut mode: Option<ConnectionMode> = None;
+    for relay in relays {
+        // parsed as the keys of the relay proxies, with the trailing slash
+        let url = Url::parse(relay.as_str())
+            .with_context(|| format!("parsing relay url {relay}"))?;
+        let relay_mode = match proxy_transport.relays.get(url.as_str())
+            .unwrap_or(&proxy_transport.default) {
+            ProxyMode::Direct => ConnectionMode::direct(),
+            ProxyMode::Socks { proxy, credentials: None } =>
+                ConnectionMode::proxy(
+                    std::net::ToSocketAddrs::to_socket_addrs(proxy.as_str())
+                        .with_context(|| format!("resolving proxy {proxy}"))?
+                        .next()
+                        .ok_or(anyhow!("proxy {proxy} without address"))?
+                ),
+            ProxyMode::Socks { credentials: Some(_), .. } => return Err(
+                anyhow!("proxy credentials not supported for signer {relay}")
+            )
+        };
+
+        match &mode {
+            Some(mode) if *mode != relay_mode => return Err(
+                anyhow!("signer relays with different proxies")
+            ),
+            _ => mode = Some(relay_mode)
+        }
+    }
+
+    Ok(mode.unwrap_or_default())
+}
+
+// private key, or a nip-46 remote signer for a bunker:// uri, connected with
+// the client key in NMINI_SIGNER_CLIENT_KEY or a new one, or the key with
+// this npub in the agent when NMINI_AGENT is set
+async fn signer_get(key: &str) -> Result<Arc<dyn NostrSigner>, Error> {
+    if ! key.starts_with("bunker://") {
+        if let Ok(socket) = std::env::var("NMINI_AGENT") {
+            // only an npub is sent to the agent, a hex key could be a private
+            // key, a private key signs here
+            if ! key.starts_with("npub1") {
+                if PublicKey::from_hex(key).is_ok() {
+                    return Err(anyhow!(
+                        "hex key with NMINI_AGENT, insert the npub of the \
+                         agent key, or the private key as nsec"
+                    ));
+                }
+                return Ok(Arc::new(Keys::parse(key)?));
+            }
+
+            let signer = AgentSigner {
+                socket,
+                public_key: PublicKey::parse(key)
+                    .with_context(|| "parsing public key of the agent key")?
+            };
+
+            // check now that the agent has the key
+            signer.request("get_public_key", None, None, None).await?;
+
+            return Ok(Arc::new(signer));
+        }
+
+        return Ok(Arc::new(Keys::parse(key)?));
+    }
+
+    let client_keys = match std::env::var("NMINI_SIGNER_CLIENT_KEY") {
+        Ok(client_key) => Keys::parse(&key_decrypt(&client_key)?)
+            .with_context(|| "parsing NMINI_SIGNER_CLIENT_KEY")?,
+        Err(_) => Keys::generate()
+    };
+
+    Ok(Arc::new(remote_signer_get(key, client_keys).await?))
+}
+
+// nip-46 remote signer of a bunker:// uri, connected with the client keys
+async fn remote_signer_get(
+    key: &str, client_keys: Keys
+) -> Result<NostrConnect, Error> {
+    let uri = NostrConnectURI::parse(key)
+        .with_context(|| "parsing bunker uri")?;
+
+    let mode = signer_connection_mode_get(uri.relays())?;
+
+    let mut signer = NostrConnect::new(
+        uri,
+        client_keys,
+        timeout_get(),
+        Some(RelayOptions::new().connection_mode(mode))
+    )?;
+    signer.auth_url_handler(SignerAuthUrlHandler);
+
+    // connect now, so a signer not answering fails before any other work
+    signer.get_public_key().await
+        .with_context(|| "connecting to remote signer")?;
+
+    Ok(signer)
+}
+
+async fn stdin_signer() -> Result<Arc<dyn NostrSigner>, Error> {
+    signer_get(&stdin_key()?).await
+}
+
+// signer in the first line of stdin, the rest of stdin returned as is
+async fn stdin_signer_split(
+) -> Result<(Arc<dyn NostrSigner>, String), Error> {
+    let (key, rest) = stdin_key_split()?;
+
+    Ok((signer_get(&key).await?, rest))
+}
+
+// line of stdin with a secret, the rest of stdin is left to be read
+fn stdin_secret_line_read() -> Result<Zeroizing<String>, Error> {
+    if std::io::stdin().is_terminal() {
+        return Err(anyhow!("stdin is empty"));
+    }
+
+    fd_secret_line_read(0)
+}
+
+// lines of stdin with secrets until the end, the blank ones skipped
+fn stdin_secret_lines_read() -> Result<Vec<Zeroizing<String>>, Error> {
+    if std::io::stdin().is_terminal() {
+        return Err(anyhow!("stdin is empty"));
+    }
+
+    let mut lines: Vec<Zeroizing<String>> = Vec::new();
+    while let Some(line) = fd_secret_line_next(0)? {
+        if ! line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+
+    Ok(lines)
+}
+
+// key in the first line of stdin, the rest of stdin returned as is
+fn stdin_key_split() -> Result<(Zeroizing<String>, String), Error> {
+    let key = stdin_secret_line_read()
+        .with_context(|| "reading key in stdin")?;
+
+    let rest = stdin_pipe_read()
+        .with_context(|| "reading stdin")?;
+
+    Ok((key_decrypt(key.trim())?, rest))
+}
+
+fn stdin_key() -> Result<Zeroizing<String>, Error> {
+    key_decrypt(stdin_secret_line_read()
         .with_context(|| "reading key in stdin")?
         .trim()
-        .to_owned()
     )
 }
 
@@ -539,6 +4206,271 @@ fn arg_relay_array(current_parameter: usize) -> Result<Vec<String>, Error> {
     Ok(relays)
 }
 
+// options are passed as a json object, other args as json arrays or strings
+fn arg_is_object(current_parameter: usize) -> bool {
+    std::env::args().nth(current_parameter)
+        .and_then(|arg| serde_json::from_str::<serde_json::Value>(&arg).ok())
+        .is_some_and(|value| value.is_object())
+}
+
+fn arg_events_send_options(
+    current_parameter: usize
+) -> Result<EventsSendOptions, Error> {
+    let mut events_send_options = EventsSendOptions::default();
+
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert send options"))?
+    ).with_context(|| "parsing send options")?;
+
+    if let Ok(value) = u64_from_serde_value(&options, "min_success") {
+        events_send_options.min_success = value as usize;
+    }
+    if let Some(value) = options.get("separate") {
+        events_send_options.separate = value
+            .as_bool()
+            .ok_or(anyhow!("separate not bool"))?;
+    }
+    if let Ok(value) = u64_from_serde_value(&options, "delay") {
+        events_send_options.delay = value;
+    }
+    if let Some(value) = options.get("self_public_key") {
+        events_send_options.self_public_key = Some(PublicKey::parse(value
+            .as_str()
+            .ok_or(anyhow!("self_public_key not str"))?
+        ).with_context(|| "parsing self_public_key")?);
+    }
+    if let Ok(value) = u64_from_serde_value(&options, "self_delay") {
+        events_send_options.self_delay = value;
+    }
+    if let Some(value) = options.get("identity") {
+        events_send_options.identity = Some(PublicKey::parse(value
+            .as_str()
+            .ok_or(anyhow!("identity not str"))?
+        ).with_context(|| "parsing identity")?);
+    }
+    if let Some(value) = options.get("new_circuit") {
+        events_send_options.new_circuit = value
+            .as_bool()
+            .ok_or(anyhow!("new_circuit not bool"))?;
+    }
+    if let Some(value) = options.get("outbox") {
+        events_send_options.outbox = Some(value
+            .as_str()
+            .ok_or(anyhow!("outbox not str"))?
+            .to_owned()
+        );
+    }
+    if let Some(value) = options.get("wrap_keys") {
+        events_send_options.wrap_keys = Some(value
+            .as_str()
+            .ok_or(anyhow!("wrap_keys not str"))?
+            .to_owned()
+        );
+    }
+
+    Ok(events_send_options)
+}
+
+fn arg_event_options(
+    current_parameter: usize
+) -> Result<EventOptions, Error> {
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert event options"))?
+    ).with_context(|| "parsing event options")?;
+
+    Ok(EventOptions {
+        pow: PowOptions::from_json(&options)?
+    })
+}
+
+fn arg_dm_events_options(
+    current_parameter: usize
+) -> Result<DmEventsOptions, Error> {
+    let mut dm_events_options = DmEventsOptions::default();
+
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert dm options"))?
+    ).with_context(|| "parsing dm options")?;
+
+    if let Ok(value) = u64_from_serde_value(&options, "created_at") {
+        dm_events_options.created_at = Some(value);
+    }
+    dm_events_options.pow = PowOptions::from_json(&options)?;
+    if let Ok(value) = u64_from_serde_value(&options, "expiration") {
+        dm_events_options.expiration = Some(value);
+    }
+    if let Some(value) = options.get("wrap_keys") {
+        dm_events_options.wrap_keys = Some(value
+            .as_str()
+            .ok_or(anyhow!("wrap_keys not str"))?
+            .to_owned()
+        );
+    }
+
+    Ok(dm_events_options)
+}
+
+fn arg_vanish_options(
+    current_parameter: usize
+) -> Result<VanishOptions, Error> {
+    let mut vanish_options = VanishOptions::default();
+
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert vanish options"))?
+    ).with_context(|| "parsing vanish options")?;
+
+    if let Some(value) = options.get("all_relays") {
+        vanish_options.all_relays = value
+            .as_bool()
+            .ok_or(anyhow!("all_relays not bool"))?;
+    }
+    if let Some(value) = options.get("reason") {
+        vanish_options.reason = value
+            .as_str()
+            .ok_or(anyhow!("reason not str"))?
+            .to_owned();
+    }
+    if let Some(value) = options.get("confirm") {
+        vanish_options.confirm = Some(value
+            .as_str()
+            .ok_or(anyhow!("confirm not str"))?
+            .to_owned()
+        );
+    }
+
+    Ok(vanish_options)
+}
+
+fn arg_agent_options(
+    current_parameter: usize
+) -> Result<AgentOptions, Error> {
+    let mut agent_options = AgentOptions::default();
+
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert agent options"))?
+    ).with_context(|| "parsing agent options")?;
+
+    if let Ok(value) = u64_from_serde_value(&options, "lifetime") {
+        agent_options.lifetime = Some(value);
+    }
+    if let Ok(value) = u64_from_serde_value(&options, "idle_timeout") {
+        agent_options.idle_timeout = Some(value);
+    }
+    if let Some(value) = options.get("confirm") {
+        agent_options.confirm = value
+            .as_bool()
+            .ok_or(anyhow!("confirm not bool"))?;
+    }
+
+    Ok(agent_options)
+}
+
+// fields of the key options that apply to the key format, log_n and
+// key_security for ncryptsec, and the ones of the action
+fn key_options_fields_get<'a>(
+    key_type_format: &KeyTypeFormat, fields: &[&'a str]
+) -> Vec<&'a str> {
+    let mut key_options_fields = fields.to_vec();
+    if matches!(key_type_format, KeyTypeFormat::Ncryptsec) {
+        key_options_fields.extend(["log_n", "key_security"]);
+    }
+
+    key_options_fields
+}
+
+// fields not in fields are rejected, rather than ignored
+fn arg_key_options(
+    current_parameter: usize, fields: &[&str]
+) -> Result<KeyOptions, Error> {
+    let mut key_options = KeyOptions::default();
+
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert key options"))?
+    ).with_context(|| "parsing key options")?;
+
+    for field in options.as_object()
+        .ok_or(anyhow!("key options not object"))?
+        .keys() {
+        if ! fields.contains(&field.as_str()) {
+            return Err(anyhow!("key option {field} not applicable"));
+        }
+    }
+
+    if let Ok(value) = u64_from_serde_value(&options, "log_n") {
+        key_options.log_n = u8::try_from(value)
+            .with_context(|| "log_n not u8")?;
+    }
+    if let Ok(value) = u64_from_serde_value(&options, "key_security") {
+        key_options.key_security = nip49::KeySecurity::try_from(
+            u8::try_from(value)
+                .with_context(|| "key_security not u8")?
+        )?;
+    }
+    if let Ok(value) = u64_from_serde_value(&options, "words") {
+        key_options.words = usize::try_from(value)
+            .with_context(|| "words not usize")?;
+    }
+    if let Ok(value) = u64_from_serde_value(&options, "account") {
+        key_options.account = u32::try_from(value)
+            .with_context(|| "account not u32")?;
+    }
+    if let Some(value) = options.get("passphrase") {
+        key_options.passphrase = value
+            .as_bool()
+            .ok_or(anyhow!("passphrase not bool"))?;
+    }
+
+    Ok(key_options)
+}
+
+fn arg_sign_options(
+    current_parameter: usize
+) -> Result<SignOptions, Error> {
+    let mut sign_options = SignOptions::default();
+
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert sign options"))?
+    ).with_context(|| "parsing sign options")?;
+
+    if let Ok(value) = u64_from_serde_value(&options, "created_at") {
+        sign_options.created_at = Some(value);
+    }
+    sign_options.pow = PowOptions::from_json(&options)?;
+    if let Ok(value) = u64_from_serde_value(&options, "expiration") {
+        sign_options.expiration = Some(value);
+    }
+
+    Ok(sign_options)
+}
+
+fn arg_delete_options(
+    current_parameter: usize
+) -> Result<DeleteOptions, Error> {
+    let mut delete_options = DeleteOptions::default();
+
+    let options: serde_json::Value = serde_json::from_str(
+        &std::env::args().nth(current_parameter)
+            .ok_or(anyhow!("insert delete options"))?
+    ).with_context(|| "parsing delete options")?;
+
+    if let Some(value) = options.get("reason") {
+        delete_options.reason = Some(value
+            .as_str()
+            .ok_or(anyhow!("reason not str"))?
+            .to_owned()
+        );
+    }
+
+    Ok(delete_options)
+}
+
 fn arg_filter_options(
     current_parameter: usize
 ) -> Result<(Option<u64>, Option<u64>), Error> {
@@ -569,41 +4501,287 @@ async fn handle_arguments() -> Result<(), Error> {
 r#"nmini action
 
 actions:
-<key> | key-convert shex|sbech32|phex|pbech32
-<events> | events-send <relays>...
-<private-key> | metadata-event <metadata-json>
-<private-key> | relay-list-event [standard|inbox] <relays>
+<key> | key-convert <key-format> [<key-options>]
+key-generate <key-format>|mnemonic [<key-options>]
+<mnemonic> | key-from-mnemonic <key-format> [<key-options>]
+key-vanity <key-format> <vanity-options>
+<private-key> <plaintext> | nip44-encrypt <public-key> [raw|base64]
+<private-key> <payload> | nip44-decrypt <public-key> [raw|base64]
+<events> | events-send <relays>... [<send-options>]
+outbox-flush <dir> [<flush-options>]
+<events> | schedule <dir> <timestamp> <relays>...
+schedule-run <dir> [<send-options>]
+<private-key> <templates> | events-sign [<sign-options>]
+<private-key> | metadata-event <metadata-json> [<event-options>]
+<private-key> | relay-list-event [standard|inbox] <relays> [<event-options>]
 events-fetch <public-key> <kinds> <relays> <filter-options>
 <rumors> | rumors-info
-<private-key> | dm-events <public-key> <message>
+[<private-key>] <events> | events-verify
+<private-key> <templates> | gift-wrap <public-keys> [<dm-options>]
+<private-key> <events> | gift-unwrap
+<private-key> | dm-events <public-key> <message> [<dm-options>]
+<private-key> | dm-react <message-json> <reaction> [<dm-options>]
 <private-key> | dm-fetch <relays>
+<private-key> | dm-fetch-legacy <relays>
 <messages> | dm-save <public-key> <dir>
+dm-purge-expired <dir>
+<private-key> | dm-retract <wrap-keys-file> [<send-options>]
+<private-key> | vanish-event <relays> [<vanish-options>]
+<private-key> | delete-event <targets> <relays> [<delete-options>]
+<private-key> | doctor <relays>
+<private-key> [<secret>] | bunker <relays> <clients>
+<private-keys> | agent <socket> [<agent-options>]
 
 args:
-private-key and public-key can be hex or bech32
+private-key and public-key can be hex or bech32, private-key can also be
+ncryptsec (nip-49), asking for its password, or a bunker:// uri of a remote
+signer (nip-46), except for key-convert, nip44-encrypt and nip44-decrypt
+key-format is shex, sbech32, phex, pbech32 or ncryptsec
+key-options is a json object that can have fields log_n (default 16) and
+key_security (0 weak, 1 medium, 2 unknown, default 2) for ncryptsec, words
+(default 24) and account (nip-06, default 0) for new mnemonics, printed with
+the npub of the account, account and passphrase (bool, asking for the bip-39
+passphrase) for key-from-mnemonic, the fields that do not apply are rejected
+vanity-options is a json object with the key-options fields and prefix and
+suffix, the characters the npub starts with after npub1 and ends with
 key can be private-key or public-key
+plaintext is raw text, or base64 for binary data, default raw, without the
+final newline
 events is a list of signed json nostr events
 rumors is a list of signed or unsiged json nostr events
+events-verify checks the gift wraps layering when the private key is in the
+first line of stdin
 relays is a json array of string urls
+templates is a list of json unsigned events with fields kind, tags, content
+and optionally created_at
+sign-options is a json object that can have fields created_at, pow,
+pow_relays and expiration (seconds)
+public-keys is a json array of public keys
 metadata-json is a json object that is parsed as metadata (nip-01, nip-24)
 kinds is a json array of kinds (uint)
 filter-options is a json object that can have fields since and until
+send-options is a json object that can have fields min_success, outbox,
+new_circuit, separate, delay, self_public_key, self_delay, identity
+(public key whose connections and circuits are used, default
+self_public_key for gift wraps and the author for the other events) and
+wrap_keys (wrap-keys-file where the relays of the gift wraps are recorded)
+flush-options is a json object that can have fields retries and backoff
+timestamp is a unix timestamp in seconds
+event-options is a json object that can have fields pow and pow_relays
+pow is a nip-13 difficulty of at most 32, raised to the min_pow_difficulty
+of the pow_relays
+dm-options is a json object that can have fields created_at, pow, pow_relays,
+expiration (seconds) and wrap_keys (file)
+wrap-keys-file is a file written by dm-events with the wrap_keys option,
+dm-retract sends the deletions to the relays recorded in it by events-send
+with the wrap_keys option
+vanish-options is a json object that can have fields all_relays (bool),
+reason and confirm (public key, instead of typing it in the terminal)
+targets is a json array of event ids and coordinates (kind:pubkey:d)
+delete-options is a json object that can have field reason
+clients is a json object of client public keys with their permissions, a
+json object that can have fields kinds (array of kinds the client can sign),
+nip04_encrypt, nip04_decrypt, nip44_encrypt and nip44_decrypt (bool)
+secret is a line after the private key, required to connect when present
+private-keys is a list of private keys, one per line
+agent-options is a json object that can have fields lifetime and
+idle_timeout (seconds) and confirm (bool, asking in the terminal of the
+agent to allow every request, the agent has to run in the foreground)
 messages is a list of json object messages
+message-json is a json object message, reactions to messages are attached to
+them by dm-fetch and dm-save
+
+environment:
+NMINI_PROXY is the socks5 proxy, [username:password@]host:port, or direct
+for clearnet connections, default 127.0.0.1:9050
+NMINI_RELAYS_PROXY is a json object of relay urls with their NMINI_PROXY
+without username and password, connections are isolated per relay and per
+identity with different socks credentials (tor IsolateSOCKSAuth)
+NMINI_PASSWORD_FD is a file descriptor from where passwords are read, a line
+per password, instead of typing them in the terminal
+NMINI_SIGNER_CLIENT_KEY is the private key of nmini as client of bunker://
+remote signers, a new one every run if not set, the signer relays are
+connected with their NMINI_PROXY without isolation
+NMINI_AGENT is the socket of an agent, private-key is then the npub of a key
+held by the agent, except for key-convert, nip44-encrypt and nip44-decrypt,
+a private key as nsec signs without the agent and a hex key is refused
+NMINI_SECRET_TTY set to 1 allows printing private keys and mnemonics in the
+terminal, otherwise stdout has to be redirected
 "#
                 );
             },
-            "key-convert" => {
+            "key-convert" => {
+                current_parameter += 1;
+                let key_type_format = KeyTypeFormat::from_str(
+                    &std::env::args().nth(current_parameter)
+                        .ok_or(anyhow!("insert key type format"))?
+                ).with_context(|| "parsing key type format")?;
+
+                let mut options = KeyOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_key_options(
+                        current_parameter,
+                        &key_options_fields_get(&key_type_format, &[])
+                    )?;
+                }
+
+                let key = stdin_key()?;
+
+                key_convert(&key, key_type_format, options)?;
+            },
+            "key-generate" => {
+                current_parameter += 1;
+                let key_type_format = KeyTypeFormat::from_str(
+                    &std::env::args().nth(current_parameter)
+                        .ok_or(anyhow!("insert key type format"))?
+                ).with_context(|| "parsing key type format")?;
+
+                let mut options = KeyOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    let fields = match key_type_format {
+                        KeyTypeFormat::Mnemonic => vec!["words", "account"],
+                        _ => key_options_fields_get(&key_type_format, &[])
+                    };
+                    options = arg_key_options(current_parameter, &fields)?;
+                }
+
+                key_generate(key_type_format, options)?;
+            },
+            "key-vanity" => {
+                current_parameter += 1;
+                let key_type_format = KeyTypeFormat::from_str(
+                    &std::env::args().nth(current_parameter)
+                        .ok_or(anyhow!("insert key type format"))?
+                ).with_context(|| "parsing key type format")?;
+
+                current_parameter += 1;
+                let options = arg_key_options(
+                    current_parameter,
+                    &key_options_fields_get(
+                        &key_type_format, &["prefix", "suffix"]
+                    )
+                )?;
+
+                let vanity_options: serde_json::Value = serde_json::from_str(
+                    &std::env::args().nth(current_parameter)
+                        .ok_or(anyhow!("insert vanity options"))?
+                ).with_context(|| "parsing vanity options")?;
+                let mut prefix = "";
+                if let Some(value) = vanity_options.get("prefix") {
+                    prefix = value.as_str().ok_or(anyhow!("prefix not str"))?;
+                }
+                let mut suffix = "";
+                if let Some(value) = vanity_options.get("suffix") {
+                    suffix = value.as_str().ok_or(anyhow!("suffix not str"))?;
+                }
+
+                key_vanity(key_type_format, prefix, suffix, options)?;
+            },
+            "key-from-mnemonic" => {
                 current_parameter += 1;
                 let key_type_format = KeyTypeFormat::from_str(
                     &std::env::args().nth(current_parameter)
                         .ok_or(anyhow!("insert key type format"))?
                 ).with_context(|| "parsing key type format")?;
 
-                let key = stdin_key()?;
+                let mut options = KeyOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_key_options(
+                        current_parameter,
+                        &key_options_fields_get(
+                            &key_type_format, &["account", "passphrase"]
+                        )
+                    )?;
+                }
+
+                let mnemonic = stdin_secret_line_read()
+                    .with_context(|| "reading mnemonic in stdin")?;
+
+                key_from_mnemonic(mnemonic.trim(), key_type_format, options)?;
+            },
+            "nip44-encrypt" | "nip44-decrypt" => {
+                current_parameter += 1;
+                let public_key = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert public key"))?;
+
+                let mut payload_format = PayloadFormat::Raw;
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    payload_format = PayloadFormat::from_str(
+                        &std::env::args().nth(current_parameter)
+                            .ok_or(anyhow!("insert payload format"))?
+                    ).with_context(|| "parsing payload format")?;
+                }
 
-                key_convert(&key, key_type_format)?;
+                // the payload is the rest of stdin, not in the args where
+                // other processes can read it
+                let (private_key, payload) = stdin_key_split()?;
+                let payload = payload.strip_suffix('\n').unwrap_or(&payload);
+
+                if arg == "nip44-encrypt" {
+                    nip44_encrypt(
+                        &private_key, &public_key, payload, payload_format
+                    )?;
+                } else {
+                    nip44_decrypt(
+                        &private_key, &public_key, payload.trim(), payload_format
+                    )?;
+                }
             },
             "events-send" => {
+                let mut relays_list: Vec<Vec<String>> = Vec::new();
+                let mut options = EventsSendOptions::default();
+                while std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    if arg_is_object(current_parameter) {
+                        options = arg_events_send_options(current_parameter)?;
+                        break;
+                    }
+                    relays_list.push(arg_relay_array(current_parameter)?);
+                }
+
+                let events: Vec<Event> = stdin_events_array()?;
+
+                events_send(events, relays_list, options, |_| Ok(())).await?;
+            },
+            "outbox-flush" => {
+                current_parameter += 1;
+                let outbox = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert outbox dir"))?;
+
+                let mut retries: u64 = 5;
+                let mut backoff: u64 = 30;
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    let options: serde_json::Value = serde_json::from_str(
+                        &std::env::args().nth(current_parameter)
+                            .ok_or(anyhow!("insert flush options"))?
+                    ).with_context(|| "parsing flush options")?;
+
+                    if let Ok(value) = u64_from_serde_value(&options, "retries") {
+                        retries = value;
+                    }
+                    if let Ok(value) = u64_from_serde_value(&options, "backoff") {
+                        backoff = value;
+                    }
+                }
+
+                outbox_flush(&outbox, retries, backoff).await?;
+            },
+            "schedule" => {
+                current_parameter += 1;
+                let dir = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert schedule dir"))?;
+
+                current_parameter += 1;
+                let send_at: u64 = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert send timestamp"))?
+                    .parse()
+                    .with_context(|| "parsing send timestamp")?;
+
                 let mut relays_list: Vec<Vec<String>> = Vec::new();
                 while std::env::args().len() - current_parameter > 1 {
                     current_parameter += 1;
@@ -612,7 +4790,39 @@ messages is a list of json object messages
 
                 let events: Vec<Event> = stdin_events_array()?;
 
-                events_send(events, relays_list).await?;
+                schedule(events, &dir, send_at, relays_list)?;
+            },
+            "schedule-run" => {
+                current_parameter += 1;
+                let dir = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert schedule dir"))?;
+
+                let mut options = EventsSendOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_events_send_options(current_parameter)?;
+                }
+
+                schedule_run(&dir, options).await?;
+            },
+            "events-sign" => {
+                let mut options = SignOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_sign_options(current_parameter)?;
+                }
+
+                let (signer, input) = stdin_signer_split().await?;
+
+                let mut templates: Vec<EventTemplate> = Vec::new();
+                for template in serde_json::Deserializer::from_str(&input)
+                    .into_iter() {
+                    templates.push(template
+                        .with_context(|| "deserializing event template")?
+                    );
+                }
+
+                events_sign(&signer, templates, options).await?;
             },
             "metadata-event" => {
                 current_parameter += 1;
@@ -621,9 +4831,15 @@ messages is a list of json object messages
                         .ok_or(anyhow!("insert metadata json"))?
                 ).with_context(|| "parsing metadata json")?;
 
-                let private_key = stdin_key()?;
+                let mut options = EventOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_event_options(current_parameter)?;
+                }
+
+                let signer = stdin_signer().await?;
 
-                metadata_event(metadata, &private_key)?;
+                metadata_event(metadata, &signer, options).await?;
             },
             "relay-list-event" => {
                 current_parameter += 1;
@@ -639,9 +4855,17 @@ messages is a list of json object messages
                 current_parameter += 1;
                 let relays = arg_relay_array(current_parameter)?;
 
-                let private_key = stdin_key()?;
+                let mut options = EventOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_event_options(current_parameter)?;
+                }
+
+                let signer = stdin_signer().await?;
 
-                relay_list_event(relay_type, &private_key, relays)?;
+                relay_list_event(
+                    relay_type, &signer, relays, options
+                ).await?;
             },
             "events-fetch" => {
                 current_parameter += 1;
@@ -669,6 +4893,74 @@ messages is a list of json object messages
 
                 rumors_info(rumors)?;
             }
+            "events-verify" => {
+                // zeroized as the first line can be a key
+                let stdin = Zeroizing::new(stdin_pipe_read()
+                    .with_context(|| "reading events in stdin")?
+                );
+                let (line, rest) = stdin.split_once('\n')
+                    .unwrap_or((&stdin, ""));
+
+                // the first line is the signer if it parses as one
+                let key = line.trim();
+                let (signer, input) = if Keys::parse(key).is_ok()
+                    || key.starts_with("ncryptsec1")
+                    || key.starts_with("bunker://")
+                    || (std::env::var("NMINI_AGENT").is_ok()
+                        && PublicKey::parse(key).is_ok()) {
+                    (Some(signer_get(&key_decrypt(key)?).await?), rest)
+                } else {
+                    (None, stdin.as_str())
+                };
+
+                let mut events: Vec<serde_json::Value> = Vec::new();
+                for event in serde_json::Deserializer::from_str(input)
+                    .into_iter() {
+                    events.push(event
+                        .with_context(|| "deserializing event")?
+                    );
+                }
+
+                events_verify(signer, events).await?;
+            },
+            "gift-wrap" => {
+                current_parameter += 1;
+                let receivers: Vec<String> = serde_json::from_str(
+                    &std::env::args().nth(current_parameter)
+                        .ok_or(anyhow!("insert public keys array"))?
+                ).with_context(|| "parsing public keys array")?;
+
+                let mut options = DmEventsOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_dm_events_options(current_parameter)?;
+                }
+
+                let (signer, input) = stdin_signer_split().await?;
+
+                let mut templates: Vec<EventTemplate> = Vec::new();
+                for template in serde_json::Deserializer::from_str(&input)
+                    .into_iter() {
+                    templates.push(template
+                        .with_context(|| "deserializing event template")?
+                    );
+                }
+
+                gift_wrap(&signer, receivers, templates, options).await?;
+            },
+            "gift-unwrap" => {
+                let (signer, input) = stdin_signer_split().await?;
+
+                let mut wraps: Vec<Event> = Vec::new();
+                for wrap in serde_json::Deserializer::from_str(&input)
+                    .into_iter() {
+                    wraps.push(wrap
+                        .with_context(|| "deserializing event")?
+                    );
+                }
+
+                gift_unwrap(&signer, wraps).await?;
+            },
             "dm-events" => {
                 current_parameter += 1;
                 let receiver_public_key = std::env::args().nth(current_parameter)
@@ -678,9 +4970,38 @@ messages is a list of json object messages
                 let message = std::env::args().nth(current_parameter)
                     .ok_or(anyhow!("insert message"))?;
 
-                let private_key = stdin_key()?;
+                let mut options = DmEventsOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_dm_events_options(current_parameter)?;
+                }
+
+                let signer = stdin_signer().await?;
+
+                dm_events(
+                    &signer, &receiver_public_key, &message, options
+                ).await?;
+            },
+            "dm-react" => {
+                current_parameter += 1;
+                let message: JsonOrdered = serde_json::from_str(
+                    &std::env::args().nth(current_parameter)
+                        .ok_or(anyhow!("insert message"))?
+                ).with_context(|| "parsing message")?;
+
+                current_parameter += 1;
+                let reaction = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert reaction"))?;
+
+                let mut options = DmEventsOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_dm_events_options(current_parameter)?;
+                }
 
-                dm_events(&private_key, &receiver_public_key, &message).await?;
+                let signer = stdin_signer().await?;
+
+                dm_react(&signer, message, &reaction, options).await?;
             },
             "dm-fetch" => {
                 current_parameter += 1;
@@ -690,9 +5011,17 @@ messages is a list of json object messages
                 // current_parameter += 1;
                 // let (since, until) = arg_filter_options(current_parameter)?;
 
-                let private_key = stdin_key()?;
+                let signer = stdin_signer().await?;
+
+                dm_fetch(&signer, relays).await?;
+            },
+            "dm-fetch-legacy" => {
+                current_parameter += 1;
+                let relays = arg_relay_array(current_parameter)?;
+
+                let signer = stdin_signer().await?;
 
-                dm_fetch(&private_key, relays).await?;
+                dm_fetch_legacy(&signer, relays).await?;
             },
             "dm-save" => {
                 current_parameter += 1;
@@ -715,6 +5044,112 @@ messages is a list of json object messages
 
                 dm_save(messages, &public_key, &dir_save)?;
             },
+            "dm-purge-expired" => {
+                current_parameter += 1;
+                let dir_save = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert dir"))?;
+
+                dm_purge_expired(&dir_save)?;
+            },
+            "dm-retract" => {
+                current_parameter += 1;
+                let wrap_keys = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert wrap keys file"))?;
+
+                let mut options = EventsSendOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_events_send_options(current_parameter)?;
+                }
+
+                let signer = stdin_signer().await?;
+
+                dm_retract(&signer, &wrap_keys, options).await?;
+            },
+            "vanish-event" => {
+                current_parameter += 1;
+                let relays = arg_relay_array(current_parameter)?;
+
+                let mut options = VanishOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_vanish_options(current_parameter)?;
+                }
+
+                let signer = stdin_signer().await?;
+
+                vanish_event(&signer, relays, options).await?;
+            },
+            "delete-event" => {
+                current_parameter += 1;
+                let targets: Vec<String> = serde_json::from_str(
+                    &std::env::args().nth(current_parameter)
+                        .ok_or(anyhow!("insert targets array"))?
+                ).with_context(|| "parsing targets array")?;
+
+                current_parameter += 1;
+                let relays = arg_relay_array(current_parameter)?;
+
+                let mut options = DeleteOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_delete_options(current_parameter)?;
+                }
+
+                let signer = stdin_signer().await?;
+
+                delete_event(&signer, targets, relays, options).await?;
+            },
+            "doctor" => {
+                current_parameter += 1;
+                let relays = arg_relay_array(current_parameter)?;
+
+                let signer = stdin_signer().await?;
+
+                doctor(&signer, relays).await?;
+            },
+            "bunker" => {
+                current_parameter += 1;
+                let relays = arg_relay_array(current_parameter)?;
+
+                current_parameter += 1;
+                let clients: HashMap<String, BunkerPermissions> =
+                    serde_json::from_str(
+                        &std::env::args().nth(current_parameter)
+                            .ok_or(anyhow!("insert clients"))?
+                    ).with_context(|| "parsing clients")?;
+
+                // the secret is read from stdin, as in the arguments it
+                // would be visible to the other users
+                let (private_key, secret) = stdin_key_split()?;
+                let secret = Some(secret.trim().to_owned())
+                    .filter(|secret| ! secret.is_empty());
+
+                bunker(&private_key, relays, clients, secret).await?;
+            },
+            "agent" => {
+                current_parameter += 1;
+                let socket = std::env::args().nth(current_parameter)
+                    .ok_or(anyhow!("insert socket"))?;
+
+                let mut options = AgentOptions::default();
+                if std::env::args().len() - current_parameter > 1 {
+                    current_parameter += 1;
+                    options = arg_agent_options(current_parameter)?;
+                }
+
+                let input_keys = stdin_secret_lines_read()
+                    .with_context(|| "reading keys in stdin")?;
+                // allocated once, so reallocations leave no copies of the
+                // keys, the ones moved on the stack while parsing are not
+                // cleared
+                let mut keys: Vec<Keys> = Vec::with_capacity(input_keys.len());
+                for key in input_keys {
+                    keys.push(Keys::parse(&key_decrypt(key.trim())?)?);
+                }
+
+                agent(keys, &socket, options).await?;
+            },
             _ => return Err(anyhow!("argument {arg} not recognized"))
         },
         None => return Err(anyhow!("insert action"))
@@ -728,6 +5163,7 @@ async fn main() {
     unsafe {
         libc::signal(libc::SIGPIPE, libc::SIG_DFL);
     }
+    core_dumps_disable();
 
     match handle_arguments().await {
         Ok(_) => {
@@ -736,9 +5172,697 @@ async fn main() {
         Err(error_chain) => {
             eprintln!("error");
             for error in error_chain.chain().rev() {
-                eprintln!("{}", error.to_string());
+                eprintln!("{error}");
             }
             std::process::exit(1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_relay_builder::{LocalRelay, MockRelay, RelayBuilder};
+
+    // the environment variables are shared by the tests
+    static ENV_LOCK: tokio::sync::Mutex<()> =
+        tokio::sync::Mutex::const_new(());
+
+    // the relays are connected directly, the others through a proxy that is
+    // not listening
+    async fn relays_direct_set(
+        relays: &[&str]
+    ) -> tokio::sync::MutexGuard<'static, ()> {
+        let lock = ENV_LOCK.lock().await;
+
+        let relays_proxy: HashMap<&str, &str> = relays.iter()
+            .map(|relay| (*relay, "direct"))
+            .collect();
+        unsafe {
+            std::env::set_var("NMINI_PROXY", "127.0.0.1:1");
+            std::env::set_var(
+                "NMINI_RELAYS_PROXY",
+                serde_json::to_string(&relays_proxy).unwrap()
+            );
+        }
+
+        lock
+    }
+
+    // new empty directory in the temporary directory
+    fn dir_temp_get(name: &str) -> String {
+        let dir = format!(
+            "{}/nmini-test-{name}-{:016x}",
+            std::env::temp_dir().display(), rand::random::<u64>()
+        );
+        mkdir(&dir).unwrap();
+
+        dir
+    }
+
+    #[tokio::test]
+    async fn events_send_report() {
+        let relay = MockRelay::run().await.unwrap().url();
+        let relay_pow = LocalRelay::run(RelayBuilder::default().min_pow(30))
+            .await
+            .unwrap();
+        let relay_pow_url = relay_pow.url();
+        let unreachable = format!("ws://127.0.0.1:1");
+        let _lock = relays_direct_set(&[&relay, &relay_pow_url]).await;
+
+        let relays = vec![relay.clone(), relay_pow_url.clone(), unreachable];
+        let client = client_connected_relays_get(&vec![relays.clone()], "")
+            .await
+            .unwrap();
+        let event = EventBuilder::text_note("report")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+
+        let output = event_send_to(&client, &relays, &event).await.unwrap();
+        assert_eq!(output.accepted, [relay]);
+        assert_eq!(
+            output.rejected.keys().collect::<Vec<_>>(), [&relay_pow_url]
+        );
+        assert!(output.timeout.is_empty());
+        assert_eq!(output.unreachable, ["ws://127.0.0.1:1"]);
+
+        let report = events_send_report_get(0, &event.id, &output);
+        assert_eq!(report["event"], 1);
+        assert_eq!(report["id"], event.id.to_hex());
+        assert_eq!(report["unreachable"][0], "ws://127.0.0.1:1");
+    }
+
+    #[tokio::test]
+    async fn outbox_flush_entries() {
+        let relay = MockRelay::run().await.unwrap().url();
+        let unreachable = format!("ws://127.0.0.1:1");
+        let _lock = relays_direct_set(&[&relay]).await;
+
+        let outbox = dir_temp_get("outbox");
+        let entry_get = |content: &str, min_success: usize| OutboxEntry {
+            event: EventBuilder::text_note(content)
+                .sign_with_keys(&Keys::generate())
+                .unwrap(),
+            relays: vec![relay.clone(), unreachable.clone()],
+            accepted: Vec::new(),
+            min_success,
+            attempts: 1,
+            identity: None
+        };
+        let sent = entry_get("sent", 1);
+        let stuck = entry_get("stuck", 2);
+        outbox_entry_write(&outbox, &sent).unwrap();
+        outbox_entry_write(&outbox, &stuck).unwrap();
+        file_write(&format!("{outbox}/bad"), "not an entry").unwrap();
+
+        // the bad entry does not stop the others
+        assert!(outbox_flush(&outbox, 0, 0).await.is_err());
+        assert!(! path_exists(&format!("{outbox}/{}", sent.event.id)));
+        assert!(path_exists(&format!("{outbox}/bad")));
+
+        let stuck: OutboxEntry = serde_json::from_str(
+            &file_read(&format!("{outbox}/{}", stuck.event.id)).unwrap()
+        ).unwrap();
+        assert_eq!(stuck.accepted, [relay]);
+        assert_eq!(stuck.attempts, 2);
+
+        std::fs::remove_dir_all(&outbox).unwrap();
+    }
+
+    #[tokio::test]
+    async fn gift_wrap_scheduled_created_at() {
+        let signer: Arc<dyn NostrSigner> = Arc::new(Keys::generate());
+        let receiver = Keys::generate();
+
+        let created_at = Timestamp::now() + Duration::from_secs(7 * 86400);
+        let rumor = EventBuilder::new(Kind::PrivateDirectMessage, "later")
+            .tag(Tag::public_key(receiver.public_key()))
+            .custom_created_at(created_at)
+            .build(signer.get_public_key().await.unwrap());
+
+        let (wrap, _) = gift_wrap_build(
+            &signer, &receiver.public_key(), rumor, Vec::new(), 0
+        ).await.unwrap();
+        let receiver: Arc<dyn NostrSigner> = Arc::new(receiver);
+        let layers = gift_wrap_open(&receiver, &wrap).await.unwrap();
+
+        // seal and wrap are dated before the rumor, not before now
+        let earliest = created_at - nip59::RANGE_RANDOM_TIMESTAMP_TWEAK.end;
+        for event_created_at in [wrap.created_at, layers.seal.created_at] {
+            assert!(event_created_at <= created_at);
+            assert!(event_created_at > earliest);
+        }
+    }
+
+    #[tokio::test]
+    async fn schedule_run_entries() {
+        let relay = MockRelay::run().await.unwrap().url();
+        let _lock = relays_direct_set(&[&relay]).await;
+
+        let dir = dir_temp_get("schedule");
+        let event_get = |content: &str| EventBuilder::text_note(content)
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let now = Timestamp::now().as_u64();
+        let relays_list = vec![vec![relay]];
+        schedule(vec![event_get("due")], &dir, now, relays_list.clone())
+            .unwrap();
+        schedule(
+            vec![event_get("later")], &dir, now + 3600, relays_list
+        ).unwrap();
+        file_write(&format!("{dir}/bad"), "not an entry").unwrap();
+
+        // the bad entry does not stop the due one
+        assert!(schedule_run(&dir, EventsSendOptions::default())
+            .await
+            .is_err());
+        let entries: Vec<String> = dir_files_get(&dir).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().any(|entry| entry.ends_with("/bad")));
+        assert!(entries.iter().any(|entry| {
+            entry.contains(&format!("/{}-", now + 3600))
+        }));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn proxy_mode_from_str() {
+        assert!(matches!(
+            ProxyMode::from_str("direct").unwrap(), ProxyMode::Direct
+        ));
+
+        let ProxyMode::Socks { proxy, credentials } =
+            ProxyMode::from_str("localhost:9050").unwrap() else {
+            panic!("not socks");
+        };
+        assert_eq!(proxy, "localhost:9050");
+        assert!(credentials.is_none());
+
+        let mode = ProxyMode::from_str("user:pass:word@[::1]:9050").unwrap();
+        let ProxyMode::Socks { proxy, credentials } = &mode else {
+            panic!("not socks");
+        };
+        assert_eq!(proxy, "[::1]:9050");
+        assert_eq!(
+            credentials,
+            &Some((format!("user"), format!("pass:word")))
+        );
+        assert!(! format!("{mode:?}").contains("pass"));
+
+        for proxy in ["localhost", ":9050", "localhost:port", "user@host:1"] {
+            assert!(ProxyMode::from_str(proxy).is_err(), "{proxy}");
+        }
+    }
+
+    #[test]
+    fn isolation() {
+        let a = Keys::generate().public_key();
+        let b = Keys::generate().public_key();
+
+        assert_eq!(
+            isolation_get(&[a, b, a], false), isolation_get(&[b, a], false)
+        );
+        assert_ne!(isolation_get(&[a], false), isolation_get(&[b], false));
+        assert_eq!(isolation_get(&[], false), "");
+
+        let new_circuit = isolation_get(&[a], true);
+        assert!(new_circuit.starts_with(&format!("{}:", a.to_hex())));
+        assert_ne!(new_circuit, isolation_get(&[a], true));
+    }
+
+    #[test]
+    fn unsigned_event_pow_difficulty() {
+        let event = EventBuilder::text_note("pow")
+            .build(Keys::generate().public_key());
+
+        let event = unsigned_event_pow(event, 12).unwrap();
+        let id = event.id.unwrap();
+        assert!(nip13::get_leading_zero_bits(id.as_bytes()) >= 12);
+        assert_eq!(
+            id,
+            EventId::new(
+                &event.pubkey, &event.created_at, &event.kind, &event.tags,
+                &event.content
+            )
+        );
+        assert!(event.tags.iter().any(|tag| {
+            tag.kind().to_string() == "nonce"
+                && tag.as_slice().get(2).map(String::as_str) == Some("12")
+        }));
+    }
+
+    fn message_get(value: serde_json::Value) -> JsonOrdered {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn message_expiration() {
+        let message = message_get(serde_json::json!({
+            "tags": [["p", "x"], ["expiration", "1700000000"]]
+        }));
+        assert_eq!(message_expiration_get(&message).unwrap(), Some(1700000000));
+        assert!(message_is_expired(&message).unwrap());
+
+        // a malformed expiration is as if it was missing
+        let message = message_get(serde_json::json!({
+            "tags": [["expiration", "soon"], ["expiration"]]
+        }));
+        assert_eq!(message_expiration_get(&message).unwrap(), None);
+        assert!(! message_is_expired(&message).unwrap());
+
+        let message = message_get(serde_json::json!({"tags": "x"}));
+        assert!(message_expiration_get(&message).is_err());
+    }
+
+    fn event_get(kind: Kind, tags: &[&[&str]]) -> Event {
+        let tags = tags.iter().map(|tag| Tag::parse(tag.to_vec()).unwrap());
+
+        EventBuilder::new(kind, "")
+            .tags(tags)
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+    }
+
+    #[test]
+    fn event_tags_errors() {
+        let relay_list = event_get(Kind::RelayList, &[&["r", "not a relay"]]);
+        assert_eq!(
+            event_tags_errors_get(&relay_list),
+            vec![format!("malformed tag r,not a relay")]
+        );
+
+        // r tags are any reference outside relay lists
+        let note = event_get(
+            Kind::TextNote, &[&["r", "not a relay"], &["nonce", "1"]]
+        );
+        assert!(event_tags_errors_get(&note).is_empty());
+
+        let note = event_get(
+            Kind::TextNote, &[&["nonce", "1", "255"], &["e", "x"]]
+        );
+        assert_eq!(
+            event_tags_errors_get(&note),
+            vec![
+                format!("nonce target 255 not reached"),
+                format!("malformed tag e,x")
+            ]
+        );
+
+        let reaction = event_get(Kind::Reaction, &[]);
+        assert_eq!(
+            event_tags_errors_get(&reaction),
+            vec![format!("kind 7 without e or a tag")]
+        );
+    }
+
+    #[test]
+    fn nip44_round_trip() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let other = Keys::generate();
+        let sender_private_key = sender.secret_key().to_secret_hex();
+        let receiver_private_key = receiver.secret_key().to_secret_hex();
+        let sender_public_key = sender.public_key().to_hex();
+        let receiver_public_key = receiver.public_key().to_hex();
+
+        let payload = nip44_payload_get(
+            &sender_private_key, &receiver_public_key, "multi\nline",
+            PayloadFormat::Raw
+        ).unwrap();
+        assert_eq!(
+            nip44_plaintext_get(
+                &receiver_private_key, &sender_public_key, &payload,
+                PayloadFormat::Raw
+            ).unwrap(),
+            "multi\nline"
+        );
+        assert!(nip44_plaintext_get(
+            &receiver_private_key, &other.public_key().to_hex(), &payload,
+            PayloadFormat::Raw
+        ).is_err());
+
+        // binary plaintext, not utf-8
+        let payload = nip44_payload_get(
+            &sender_private_key, &receiver_public_key, "/wA=",
+            PayloadFormat::Base64
+        ).unwrap();
+        assert_eq!(
+            nip44_plaintext_get(
+                &receiver_private_key, &sender_public_key, &payload,
+                PayloadFormat::Base64
+            ).unwrap(),
+            "/wA="
+        );
+        assert!(nip44_plaintext_get(
+            &receiver_private_key, &sender_public_key, &payload,
+            PayloadFormat::Raw
+        ).is_err());
+    }
+
+    #[tokio::test]
+    async fn gift_wrap_layers() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let signer: Arc<dyn NostrSigner> = Arc::new(sender.clone());
+        let receiver_signer: Arc<dyn NostrSigner> = Arc::new(receiver.clone());
+
+        let rumor = EventBuilder::new(Kind::TextNote, "rumor")
+            .build(sender.public_key());
+        let (wrap, _) = gift_wrap_build(
+            &signer, &receiver.public_key(), rumor, Vec::new(), 0
+        ).await.unwrap();
+
+        let layers = gift_wrap_open(&receiver_signer, &wrap).await.unwrap();
+        assert_eq!(layers.seal.pubkey, sender.public_key());
+        assert!(layers.seal_errors.is_empty());
+        assert!(layers.rumor_errors.is_empty());
+        assert_eq!(layers.rumor["content"], "rumor");
+
+        // only the receiver can open it
+        assert!(gift_wrap_open(&signer, &wrap).await.is_err());
+
+        // seal with tags, of a rumor signed and of an other pubkey
+        let rumor = EventBuilder::new(Kind::TextNote, "forged")
+            .sign_with_keys(&Keys::generate())
+            .unwrap();
+        let seal = EventBuilder::new(
+            Kind::Seal,
+            nip44::encrypt(
+                sender.secret_key(), &receiver.public_key(), rumor.as_json(),
+                nip44::Version::default()
+            ).unwrap()
+        )
+            .tag(Tag::public_key(receiver.public_key()))
+            .sign_with_keys(&sender)
+            .unwrap();
+        let ephemeral_keys = Keys::generate();
+        let wrap = EventBuilder::new(
+            Kind::GiftWrap,
+            nip44::encrypt(
+                ephemeral_keys.secret_key(), &receiver.public_key(),
+                seal.as_json(), nip44::Version::default()
+            ).unwrap()
+        )
+            .tag(Tag::public_key(receiver.public_key()))
+            .sign_with_keys(&ephemeral_keys)
+            .unwrap();
+
+        let layers = gift_wrap_open(&receiver_signer, &wrap).await.unwrap();
+        assert_eq!(layers.seal_errors, [format!("seal with tags")]);
+        assert_eq!(
+            layers.rumor_errors,
+            [
+                format!("rumor signed"),
+                format!("rumor pubkey different from seal pubkey")
+            ]
+        );
+    }
+
+    #[test]
+    fn message_reactions() {
+        let author = Keys::generate().public_key().to_hex();
+        let peer = Keys::generate().public_key().to_hex();
+        let other = Keys::generate().public_key().to_hex();
+
+        let mut message = message_get(serde_json::json!({
+            "id": "m",
+            "pubkey": {"hex": author},
+            "tags": [["p", peer]]
+        }));
+        let reaction = |id: &str, pubkey: &str| message_get(serde_json::json!({
+            "id": id,
+            "pubkey": {"hex": pubkey},
+            "kind": 7,
+            "tags": [["e", "m"]]
+        }));
+
+        assert!(message_reaction_allowed(&message, &reaction("r", &author))
+            .unwrap());
+        assert!(message_reaction_allowed(&message, &reaction("r", &peer))
+            .unwrap());
+        assert!(! message_reaction_allowed(&message, &reaction("r", &other))
+            .unwrap());
+
+        // the author of a reaction is the one of the seal
+        let mut sealed = reaction("r", &peer);
+        sealed.insert(format!("sealed"), serde_json::json!({"hex": other}));
+        assert!(! message_reaction_allowed(&message, &sealed).unwrap());
+
+        assert_eq!(
+            message_reaction_target_get(&reaction("r", &peer)).unwrap(),
+            Some(format!("m"))
+        );
+
+        assert!(message_reactions_merge(
+            &mut message, vec![reaction("r1", &peer), reaction("r2", &author)]
+        ).unwrap());
+        assert!(! message_reactions_merge(
+            &mut message, vec![reaction("r1", &peer)]
+        ).unwrap());
+        assert!(message_reactions_merge(
+            &mut message, vec![reaction("r1", &peer), reaction("r3", &peer)]
+        ).unwrap());
+        let ids: Vec<&str> = message["reactions"].as_array().unwrap().iter()
+            .map(|reaction| reaction["id"].as_str().unwrap())
+            .collect();
+        assert_eq!(ids, ["r1", "r2", "r3"]);
+    }
+
+    #[tokio::test]
+    async fn nip49_round_trip() {
+        let _lock = ENV_LOCK.lock().await;
+
+        // the passwords are read from a pipe
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let passwords = b"nostr\npassword\npassword\n";
+        assert_eq!(
+            unsafe { libc::write(fds[1], passwords.as_ptr().cast(), 24) },
+            24
+        );
+        unsafe {
+            libc::close(fds[1]);
+            std::env::set_var("NMINI_PASSWORD_FD", fds[0].to_string());
+        }
+
+        // nip-49 test vector
+        let key = key_decrypt(
+            "ncryptsec1qgg9947rlpvqu76pj5ecreduf9jxhselq2nae2kghhvd5g7dgjtcxfq\
+             td67p9m0w57lspw8gsq6yphnm8623nsl8xn9j4jdzz84zm3frztj3z7s35vpzmqf6\
+             ksu8r89qk5z2zxfmu5gv8th8wclt0h4p"
+        ).unwrap();
+        let keys = Keys::parse(&key).unwrap();
+        assert_eq!(
+            keys.secret_key().to_secret_hex(),
+            "3501454135014541350145413501453fefb02227e449e57cf4d3a3ce05378683"
+        );
+
+        let options = KeyOptions {
+            log_n: 4,
+            key_security: nip49::KeySecurity::Medium,
+            ..Default::default()
+        };
+        let ncryptsec = keys_format_get(
+            &keys, &KeyTypeFormat::Ncryptsec, &options
+        ).unwrap();
+        let encrypted_secret_key =
+            nip49::EncryptedSecretKey::from_bech32(&ncryptsec).unwrap();
+        assert_eq!(encrypted_secret_key.log_n(), 4);
+        assert_eq!(
+            encrypted_secret_key.key_security(), nip49::KeySecurity::Medium
+        );
+        assert_eq!(
+            Keys::parse(&key_decrypt(&ncryptsec).unwrap()).unwrap(), keys
+        );
+
+        unsafe {
+            std::env::remove_var("NMINI_PASSWORD_FD");
+            libc::close(fds[0]);
+        }
+    }
+
+    // bech32 alphabet, length and padding checks of the vanity npub
+    #[test]
+    fn vanity_prefix() {
+        assert_eq!(vanity_prefix_get("npub1qq", "").unwrap(), "npub1qq");
+        assert_eq!(vanity_prefix_get("qq", "l").unwrap(), "npub1qq");
+        // b, i, o and 1 are not in the bech32 alphabet
+        for prefix in ["b", "i", "o", "1", "Q"] {
+            assert!(vanity_prefix_get(prefix, "").is_err(), "{prefix}");
+        }
+        assert!(vanity_prefix_get("", "b").is_err());
+        assert!(vanity_prefix_get(&"q".repeat(58), "").is_ok());
+        assert!(vanity_prefix_get(&"q".repeat(58), "q").is_err());
+        // the last character of the key data is q or s
+        assert!(vanity_prefix_get("", "sqqqqqq").is_ok());
+        assert!(vanity_prefix_get("", "pqqqqqq").is_err());
+        assert!(vanity_prefix_get("", "pqqqqq").is_ok());
+        let prefix = "q".repeat(51);
+        assert!(vanity_prefix_get(&format!("{prefix}s"), "").is_ok());
+        assert!(vanity_prefix_get(&format!("{prefix}p"), "").is_err());
+    }
+
+    // nip-06 test vector
+    #[test]
+    fn nip06_vector() {
+        let mnemonic = "leader monkey parrot ring guide accident before \
+            fence cannon height naive bean";
+        let keys = mnemonic_keys_get(mnemonic, &KeyOptions::default())
+            .unwrap();
+
+        assert_eq!(
+            keys.secret_key().to_secret_hex(),
+            "7f7ff03d123792d6ac594bfa67bf6d0c0ab55b6b1fdb6249303fe861f1ccba9a"
+        );
+        assert_eq!(
+            keys.public_key().to_hex(),
+            "17162c921dc4d2518f9a101db33695df1afb56ab82f5ff3e5da6eec3ca5cd917"
+        );
+
+        let options = KeyOptions { account: 1, ..KeyOptions::default() };
+        assert_ne!(
+            mnemonic_keys_get(mnemonic, &options).unwrap().public_key(),
+            keys.public_key()
+        );
+    }
+
+    // per-relay proxy of the nostr connect relays
+    #[tokio::test]
+    async fn signer_connection_mode() {
+        let _lock = relays_direct_set(&["ws://127.0.0.1:7777"]).await;
+
+        // the first relay direct, the second with the default proxy
+        let relays = [
+            RelayUrl::parse("ws://127.0.0.1:7777").unwrap(),
+            RelayUrl::parse("ws://127.0.0.1:7778").unwrap()
+        ];
+        assert_eq!(
+            signer_connection_mode_get(&relays[..1]).unwrap(),
+            ConnectionMode::direct()
+        );
+        assert_eq!(
+            signer_connection_mode_get(&relays[1..]).unwrap(),
+            ConnectionMode::proxy("127.0.0.1:1".parse().unwrap())
+        );
+        assert!(signer_connection_mode_get(&relays).is_err());
+    }
+
+    // requests to the agent with its key, and with a key it does not hold
+    #[tokio::test]
+    async fn agent_requests() {
+        let keys = Keys::generate();
+        let peer = Keys::generate();
+        let socket = format!("{}/agent", dir_temp_get("agent"));
+
+        let agent_keys = vec![keys.clone()];
+        let agent_socket = socket.clone();
+        let agent = tokio::spawn(async move {
+            agent(agent_keys, &agent_socket, AgentOptions {
+                idle_timeout: Some(1),
+                ..Default::default()
+            }).await
+        });
+        while ! path_exists(&socket) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        let signer = AgentSigner {
+            socket: socket.clone(),
+            public_key: keys.public_key()
+        };
+        assert_eq!(
+            signer.request("get_public_key", None, None, None).await.unwrap(),
+            serde_json::json!(keys.public_key())
+        );
+
+        let event = EventBuilder::text_note("agent")
+            .sign(&signer)
+            .await
+            .unwrap();
+        event.verify().unwrap();
+        assert_eq!(event.pubkey, keys.public_key());
+
+        let payload = signer.nip44_encrypt(&peer.public_key(), "hello")
+            .await
+            .unwrap();
+        assert_eq!(
+            peer.nip44_decrypt(&keys.public_key(), &payload).await.unwrap(),
+            "hello"
+        );
+
+        // unknown methods and keys are denied
+        assert!(signer.request("sign", None, None, None).await.is_err());
+        let signer = AgentSigner {
+            socket: socket.clone(),
+            public_key: peer.public_key()
+        };
+        let error = EventBuilder::text_note("agent")
+            .sign(&signer)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("key not in the agent"));
+
+        // the agent exits when idle and removes its socket
+        agent.await.unwrap().unwrap();
+        assert!(! path_exists(&socket));
+    }
+
+    // nip-46 against a local signer, the bunker action, on a mock relay
+    // reached directly with a per-relay proxy
+    #[tokio::test]
+    async fn nip46_local_signer() {
+        let relay = MockRelay::run().await.unwrap().url();
+        let _lock = relays_direct_set(&[&relay]).await;
+        let keys = Keys::generate();
+        let client_keys = Keys::generate();
+        let peer = Keys::generate();
+
+        let clients = HashMap::from([(
+            client_keys.public_key().to_hex(),
+            BunkerPermissions {
+                kinds: vec![Kind::TextNote],
+                nip44_encrypt: true,
+                nip44_decrypt: true,
+                ..Default::default()
+            }
+        )]);
+        let secret = format!("{:032x}", rand::random::<u128>());
+        let private_key = keys.secret_key().to_secret_hex();
+        let bunker_secret = Some(secret.clone());
+        let bunker_relay = relay.clone();
+        tokio::spawn(async move {
+            bunker(&private_key, vec![bunker_relay], clients, bunker_secret)
+                .await
+                .unwrap();
+        });
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let uri = format!(
+            "bunker://{}?relay={relay}&secret={secret}", keys.public_key()
+        );
+        let signer = remote_signer_get(&uri, client_keys).await.unwrap();
+        assert_eq!(signer.get_public_key().await.unwrap(), keys.public_key());
+
+        let event = EventBuilder::text_note("remote")
+            .sign(&signer)
+            .await
+            .unwrap();
+        event.verify().unwrap();
+        assert_eq!(event.pubkey, keys.public_key());
+
+        let payload = signer.nip44_encrypt(&peer.public_key(), "hello")
+            .await
+            .unwrap();
+        assert_eq!(
+            peer.nip44_decrypt(&keys.public_key(), &payload).await.unwrap(),
+            "hello"
+        );
+
+        // kinds not allowed are refused
+        assert!(EventBuilder::new(Kind::Metadata, "{}")
+            .sign(&signer)
+            .await
+            .is_err());
+    }
+}